@@ -1,7 +1,21 @@
+use std::env;
+use std::path::PathBuf;
+
 fn build_proto() {
-    tonic_build::compile_protos("proto/arcanist.proto")
+    // include the file descriptor set for gRPC reflection support
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("arcanist_descriptor.bin"))
+        .compile(
+            &[
+                "proto/arcanist.proto",
+                "proto/google/rpc/status.proto",
+                "proto/google/rpc/error_details.proto",
+            ],
+            &["proto"],
+        )
         .unwrap_or_else(|e| panic!("failed to compile proto: {}", e));
-    println!("cargo:rerun-if-changed=proto/arcanist.proto");
+    println!("cargo:rerun-if-changed=proto");
 }
 
 fn main() {
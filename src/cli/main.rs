@@ -1,14 +1,13 @@
+use std::fs;
 use std::io;
 use std::net::SocketAddr;
 use std::process;
-use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use arcanist::{Client, ClientConfig, ErrorDetails, Reason, REQUEST_ID_KEY};
 use clap::{Arg, ArgMatches, Command};
 use pkgcraft::config::Config as PkgcraftConfig;
-use tokio::net::UnixStream;
-use tonic::transport::{Channel, Endpoint, Uri};
-use tower::service_fn;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 use tracing_subscriber::{filter::LevelFilter, fmt};
 use url::Url;
 
@@ -19,8 +18,6 @@ mod argparse;
 mod settings;
 mod subcmds;
 
-pub type Client = arcanist::Client<Channel>;
-
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
     Command::new(env!("CARGO_BIN_NAME"))
@@ -66,6 +63,26 @@ pub fn cmd() -> Command<'static> {
             .long("connect")
             .value_name("URL")
             .help("connect to given arcanist instance"))
+        .arg(Arg::new("tls-ca")
+            .takes_value(true)
+            .forbid_empty_values(true)
+            .long("tls-ca")
+            .value_name("PATH")
+            .help("CA certificate used to verify https connections"))
+        .arg(Arg::new("tls-cert")
+            .takes_value(true)
+            .forbid_empty_values(true)
+            .long("tls-cert")
+            .value_name("PATH")
+            .requires("tls-key")
+            .help("client certificate for https connections"))
+        .arg(Arg::new("tls-key")
+            .takes_value(true)
+            .forbid_empty_values(true)
+            .long("tls-key")
+            .value_name("PATH")
+            .requires("tls-cert")
+            .help("private key for the client certificate"))
         .arg(Arg::new("timeout")
             .takes_value(true)
             .forbid_empty_values(true)
@@ -107,6 +124,16 @@ fn load_settings() -> Result<(Settings, PkgcraftConfig, ArgMatches)> {
         };
     }
 
+    if let Some(path) = args.value_of("tls-ca") {
+        settings.tls_ca = path.to_string();
+    }
+    if let Some(path) = args.value_of("tls-cert") {
+        settings.tls_cert = path.to_string();
+    }
+    if let Some(path) = args.value_of("tls-key") {
+        settings.tls_key = path.to_string();
+    }
+
     // defaults to warning level
     let tracing_filter = match settings.verbosity {
         i32::MIN..=-2 => LevelFilter::OFF,
@@ -126,61 +153,141 @@ fn load_settings() -> Result<(Settings, PkgcraftConfig, ArgMatches)> {
     Ok((settings, config, args))
 }
 
+// Create the TLS config for https connections.
+fn tls_config(settings: &Settings) -> Result<ClientTlsConfig> {
+    let read = |path: &str| fs::read(path).context(format!("failed reading: {path}"));
+    let mut config = ClientTlsConfig::new();
+    if !settings.tls_ca.is_empty() {
+        config = config.ca_certificate(Certificate::from_pem(read(&settings.tls_ca)?));
+    }
+    match (settings.tls_cert.is_empty(), settings.tls_key.is_empty()) {
+        (true, true) => (),
+        (false, false) => {
+            let identity = Identity::from_pem(read(&settings.tls_cert)?, read(&settings.tls_key)?);
+            config = config.identity(identity);
+        }
+        _ => bail!("TLS client identity requires both a certificate and key"),
+    }
+    Ok(config)
+}
+
+// Create the client config used to connect to a given unix domain socket path or URL.
+fn client_config(url: &str, settings: &Settings, timeout: u64) -> Result<ClientConfig> {
+    let tls = match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "https" => Some(tls_config(settings)?),
+        _ => None,
+    };
+    Ok(ClientConfig {
+        timeout: Some(timeout),
+        idle_timeout: Some(settings.spawn_idle_timeout).filter(|x| *x > 0),
+        tls,
+        user_agent: format!("{}-{}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION")),
+        request_id: None,
+    })
+}
+
+/// Connect to an arcanist instance at a given unix domain socket path or URL.
+pub async fn connect(url: String, settings: &Settings, timeout: u64) -> Result<Client> {
+    let config = client_config(&url, settings, timeout)?;
+    Ok(Client::connect_with(url, &config).await?)
+}
+
 #[tokio::main]
 async fn try_main() -> Result<()> {
     let (settings, config, args) = load_settings()?;
-    let user_agent = format!("{}-{}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
     let timeout = args
         .value_of("timeout")
         .unwrap_or_default()
         .parse::<u64>()
         .unwrap();
 
+    // daemon management handles connecting itself to avoid spawning arcanist
+    if let Some(("daemon", m)) = args.subcommand() {
+        return subcmds::daemon::run(m, &settings, &config, timeout).await;
+    }
+
     // use unix domain socket by default if no connection URL is given
-    let url = match settings.url.is_empty() {
-        false => settings.url.clone(),
+    let mut client = match settings.url.is_empty() {
+        false => connect(settings.url.clone(), &settings, timeout).await?,
         true => {
             let path = config.path.run.join("arcanist.sock");
-            arcanist::connect_or_spawn(&path, Some(timeout)).await?
+            let config = client_config(&path.to_string_lossy(), &settings, timeout)?;
+            Client::connect_or_spawn(&path, &config).await?
         }
     };
+    subcmds::run(&args, &mut client, &settings).await
+}
 
-    // connect to arcanist
-    let channel: Channel = match Url::parse(&url) {
-        Err(_) => {
-            let error = format!("failed connecting to arcanist socket: {url}");
-            Endpoint::from_static("http://[::]")
-                .user_agent(user_agent)?
-                .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(url.clone())))
-                .await
-                .context(error)?
-        }
-        Ok(_) => {
-            let error = format!("failed connecting to arcanist: {url}");
-            Endpoint::from_shared(url)?
-                .connect_timeout(Duration::from_secs(timeout))
-                .user_agent(user_agent)?
-                .connect()
-                .await
-                .context(error)?
+// Return the exit status for a failed request, using sysexits(3) values where applicable.
+fn exit_status(reason: Reason) -> i32 {
+    match reason {
+        Reason::InvalidTarget | Reason::NotInstalled => 65,
+        Reason::RepoNotFound | Reason::JobNotFound => 66,
+        Reason::RepoExists => 73,
+        Reason::ShuttingDown => 75,
+        Reason::PermissionDenied => 77,
+        Reason::RepoConfig | Reason::InvalidConfig => 78,
+        Reason::BuildFailed | Reason::RemoveFailed | Reason::SyncFailed => 3,
+        Reason::PackageRequired => 3,
+        Reason::JobCancelled => 4,
+        Reason::Internal => 70,
+    }
+}
+
+// Return a hint on resolving a failed request.
+fn hint(details: &ErrorDetails) -> Option<String> {
+    let job = details.metadata.get("job");
+    let hint = match details.reason {
+        Reason::InvalidTarget => {
+            "targets use package dependency syntax, e.g. cat/pkg or >=cat/pkg-1"
         }
+        Reason::NotInstalled => "only packages installed by arcanist can be removed",
+        Reason::PackageRequired => "remove the packages requiring them at the same time",
+        Reason::RepoNotFound => "list configured repos with `pakt repo list`",
+        Reason::RepoExists => "remove the existing repo with `pakt repo del` first",
+        Reason::JobNotFound => "list jobs with `pakt jobs` or `pakt history`",
+        Reason::PermissionDenied => "administrative requests require arcanist's admin access",
+        Reason::ShuttingDown => "arcanist is shutting down, retry once it restarts",
+        Reason::InvalidConfig => "fix the arcanist config file and reload again",
+        Reason::BuildFailed | Reason::RemoveFailed | Reason::SyncFailed => match job {
+            Some(id) => return Some(format!("view the job output with `pakt log {id}`")),
+            None => return None,
+        },
+        Reason::RepoConfig | Reason::JobCancelled | Reason::Internal => return None,
     };
-
-    let mut client: Client = arcanist::Client::new(channel);
-    subcmds::run(&args, &mut client, &settings).await
+    Some(hint.to_string())
 }
 
 fn main() {
-    // extract error message from tonic status responses
     if let Err(error) = try_main() {
         eprintln!("error: {error}\n");
         error
             .chain()
             .skip(1)
-            .for_each(|cause| match cause.downcast_ref() {
-                Some(e @ tonic::Status { .. }) => eprintln!("caused by: {}", e.message()),
-                _ => eprintln!("caused by: {cause}"),
-            });
-        process::exit(1);
+            .for_each(|cause| eprintln!("caused by: {cause}"));
+
+        let mut code = 1;
+        let status = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<arcanist::Error>())
+            .and_then(|e| e.status());
+        if let Some(status) = status {
+            if let Some(details) = ErrorDetails::from_status(status) {
+                if let Some(hint) = hint(&details) {
+                    eprintln!("hint: {hint}");
+                }
+                code = exit_status(details.reason);
+            }
+
+            // failed requests can be found in arcanist logs via the ID echoed back by arcanist
+            let id = status
+                .metadata()
+                .get(REQUEST_ID_KEY)
+                .and_then(|v| v.to_str().ok());
+            if let Some(id) = id {
+                eprintln!("request id: {id}");
+            }
+        }
+        process::exit(code);
     }
 }
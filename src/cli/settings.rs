@@ -6,12 +6,39 @@ use config::{Config, Environment, File};
 use pkgcraft::config::Config as PkgcraftConfig;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+// Default to spawned instances exiting after five minutes without activity.
+fn default_spawn_idle_timeout() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub color: bool,
     pub debug: bool,
     pub verbosity: i32,
     pub url: String,
+    pub tls_ca: String,
+    pub tls_cert: String,
+    pub tls_key: String,
+    // seconds arcanist instances spawned on demand wait without activity before exiting, with
+    // zero disabling it
+    #[serde(default = "default_spawn_idle_timeout")]
+    pub spawn_idle_timeout: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            color: false,
+            debug: false,
+            verbosity: 0,
+            url: String::new(),
+            tls_ca: String::new(),
+            tls_cert: String::new(),
+            tls_key: String::new(),
+            spawn_idle_timeout: default_spawn_idle_timeout(),
+        }
+    }
 }
 
 impl Settings {
@@ -39,8 +66,13 @@ impl Settings {
             }
         }
 
-        // merge env variable overrides
-        s = s.add_source(Environment::with_prefix(&binary_upper).separator("_"));
+        // merge env variable overrides, separating nested keys with double underscores since
+        // setting names contain single ones
+        s = s.add_source(
+            Environment::with_prefix(&binary_upper)
+                .prefix_separator("_")
+                .separator("__"),
+        );
 
         // respect NO_COLOR -- https://no-color.org/
         if env::var_os("NO_COLOR").is_some() {
@@ -2,10 +2,15 @@ use anyhow::Result;
 use clap::{ArgMatches, Command};
 
 use crate::settings::Settings;
-use crate::Client;
+use arcanist::Client;
 
 mod add;
+mod cancel;
+pub mod daemon;
 mod del;
+mod history;
+mod jobs;
+mod log;
 mod repo;
 mod search;
 mod version;
@@ -14,7 +19,12 @@ mod version;
 pub fn register() -> Vec<Command<'static>> {
     vec![
         add::cmd(),
+        cancel::cmd(),
+        daemon::cmd(),
         del::cmd(),
+        history::cmd(),
+        jobs::cmd(),
+        log::cmd(),
         repo::cmd(),
         search::cmd(),
         version::cmd(),
@@ -25,7 +35,11 @@ pub async fn run(args: &ArgMatches, client: &mut Client, settings: &Settings) ->
     let (subcmd, m) = args.subcommand().unwrap();
     match subcmd {
         "add" => add::run(m, client).await,
+        "cancel" => cancel::run(m, client).await,
         "del" => del::run(m, client).await,
+        "history" => history::run(m, client).await,
+        "jobs" => jobs::run(m, client).await,
+        "log" => log::run(m, client).await,
         "repo" => repo::run(m, client, settings).await,
         "search" => search::run(m, client).await,
         "version" => version::run(client).await,
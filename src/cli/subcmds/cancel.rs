@@ -0,0 +1,30 @@
+use anyhow::{Context, Result};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::argparse::positive_int;
+use arcanist::Client;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("cancel")
+        .about("cancel jobs")
+        .disable_help_subcommand(true)
+        .arg(Arg::new("jobs")
+            .required(true)
+            .takes_value(true)
+            .multiple_values(true)
+            .value_name("JOB")
+            .validator(positive_int)
+            .help("jobs to cancel"))
+}
+
+pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
+    for id in args.values_of("jobs").unwrap().map(|s| s.parse().unwrap()) {
+        let job = client
+            .cancel(id)
+            .await
+            .context(format!("failed cancelling job: {id}"))?;
+        println!("{job}");
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use clap::{ArgMatches, Command};
+use pkgcraft::config::Config as PkgcraftConfig;
+use tokio::time::sleep;
+use url::Url;
+
+use crate::settings::Settings;
+
+mod reload;
+mod restart;
+mod start;
+mod status;
+mod stop;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("daemon")
+        .about("manage arcanist")
+        .disable_help_subcommand(true)
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(reload::cmd())
+        .subcommand(restart::cmd())
+        .subcommand(start::cmd())
+        .subcommand(status::cmd())
+        .subcommand(stop::cmd())
+}
+
+// Determine if arcanist is accepting connections at a given socket path or URL.
+fn running(url: &str) -> bool {
+    match Url::parse(url) {
+        Err(_) => UnixStream::connect(url).is_ok(),
+        Ok(url) => url
+            .socket_addrs(|| None)
+            .unwrap_or_default()
+            .iter()
+            .any(|addr| TcpStream::connect_timeout(addr, Duration::from_secs(1)).is_ok()),
+    }
+}
+
+// Verify arcanist can be started at a given socket path or URL.
+fn check_startable(url: &str) -> Result<()> {
+    ensure!(
+        Url::parse(url).is_err(),
+        "only unix domain socket instances can be started: {url}"
+    );
+    Ok(())
+}
+
+// Wait for arcanist to stop accepting connections.
+async fn wait_stopped(url: &str) {
+    while running(url) {
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+pub async fn run(
+    args: &ArgMatches,
+    settings: &Settings,
+    config: &PkgcraftConfig,
+    timeout: u64,
+) -> Result<()> {
+    // use unix domain socket by default if no connection URL is given
+    let url = match settings.url.is_empty() {
+        false => settings.url.clone(),
+        true => config.path.run.join("arcanist.sock").to_string(),
+    };
+
+    let (subcmd, m) = args.subcommand().unwrap();
+    match subcmd {
+        "reload" => reload::run(&url, settings, timeout).await,
+        "restart" => restart::run(m, &url, settings, timeout).await,
+        "start" => start::run(&url, timeout).await,
+        "status" => status::run(&url, settings, timeout).await,
+        "stop" => stop::run(m, &url, settings, timeout).await,
+        _ => panic!("unknown subcommand"),
+    }
+}
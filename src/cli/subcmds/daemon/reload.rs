@@ -0,0 +1,23 @@
+use anyhow::{Context, Result};
+use clap::Command;
+
+use crate::connect;
+use crate::settings::Settings;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("reload")
+        .about("reload arcanist config")
+}
+
+pub async fn run(url: &str, settings: &Settings, timeout: u64) -> Result<()> {
+    let mut client = connect(url.to_string(), settings, timeout).await?;
+    let changes = client.reload().await.context("failed reloading config")?;
+    if changes.is_empty() {
+        println!("no changes");
+    }
+    for change in changes {
+        println!("{change}");
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use clap::{Arg, ArgMatches, Command};
+
+use super::{start, stop};
+use crate::settings::Settings;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("restart")
+        .about("restart arcanist")
+        .arg(Arg::new("wait")
+            .long("wait")
+            .help("wait for jobs to finish instead of cancelling them"))
+}
+
+pub async fn run(args: &ArgMatches, url: &str, settings: &Settings, timeout: u64) -> Result<()> {
+    // avoid stopping instances that can't be started again
+    super::check_startable(url)?;
+    stop::stop(url, settings, timeout, args.is_present("wait")).await?;
+    start::run(url, timeout).await
+}
@@ -0,0 +1,31 @@
+use std::fs;
+
+use anyhow::Result;
+use clap::Command;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("start")
+        .about("start arcanist in the background")
+}
+
+pub async fn run(url: &str, timeout: u64) -> Result<()> {
+    super::check_startable(url)?;
+
+    if super::running(url) {
+        println!("arcanist already running at: {url}");
+        return Ok(());
+    }
+
+    // remove potentially existing, old socket file
+    fs::remove_file(url).unwrap_or_default();
+    let env: Option<Vec<(&str, &str)>> = None;
+    let args: Option<Vec<&str>> = None;
+    let (_, info) = arcanist::spawn(url, env, args, Some(timeout)).await?;
+    println!(
+        "arcanist started at: {} (pid {})",
+        info.sockets.join(", "),
+        info.pid
+    );
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use clap::Command;
+
+use crate::connect;
+use crate::settings::Settings;
+use arcanist::proto::job::State;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("status")
+        .about("show arcanist status")
+}
+
+pub async fn run(url: &str, settings: &Settings, timeout: u64) -> Result<()> {
+    if !super::running(url) {
+        println!("arcanist not running at: {url}");
+        return Ok(());
+    }
+
+    let mut client = connect(url.to_string(), settings, timeout).await?;
+    let version = client.version().await?;
+    let jobs = client.jobs().await?;
+    let active = jobs
+        .iter()
+        .filter(|job| matches!(job.state(), State::Queued | State::Running))
+        .count();
+
+    println!("arcanist running at: {url}");
+    println!("version: {version}");
+    println!("active jobs: {active}");
+    Ok(())
+}
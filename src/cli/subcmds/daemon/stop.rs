@@ -0,0 +1,35 @@
+use anyhow::{Context, Result};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::connect;
+use crate::settings::Settings;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("stop")
+        .about("stop arcanist")
+        .arg(Arg::new("wait")
+            .long("wait")
+            .help("wait for jobs to finish instead of cancelling them"))
+}
+
+// Request arcanist to shut down, waiting until it stops.
+pub async fn stop(url: &str, settings: &Settings, timeout: u64, wait_for_jobs: bool) -> Result<()> {
+    if !super::running(url) {
+        println!("arcanist not running at: {url}");
+        return Ok(());
+    }
+
+    let mut client = connect(url.to_string(), settings, timeout).await?;
+    client
+        .shutdown(wait_for_jobs)
+        .await
+        .context("failed stopping arcanist")?;
+    super::wait_stopped(url).await;
+    println!("arcanist stopped");
+    Ok(())
+}
+
+pub async fn run(args: &ArgMatches, url: &str, settings: &Settings, timeout: u64) -> Result<()> {
+    stop(url, settings, timeout, args.is_present("wait")).await
+}
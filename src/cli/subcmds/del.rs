@@ -1,8 +1,9 @@
 use anyhow::Result;
 use clap::{Arg, ArgMatches, Command};
+use futures::TryStreamExt;
 
-use crate::Client;
-use arcanist::proto::ListRequest;
+use arcanist::proto::unmerge_event::Kind;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -18,16 +19,14 @@ pub fn cmd() -> Command<'static> {
 }
 
 pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
-    let pkgs: Vec<String> = args
-        .values_of("pkgs")
-        .unwrap()
-        .map(|s| s.to_string())
-        .collect();
-    let request = tonic::Request::new(ListRequest { data: pkgs });
-    let response = client.remove_packages(request).await?;
-    let mut stream = response.into_inner();
-    while let Some(response) = stream.message().await? {
-        println!("{}", response.data);
+    let mut stream = client.remove(args.values_of("pkgs").unwrap()).await?;
+    while let Some(event) = stream.try_next().await? {
+        let files = event.files.len();
+        match (event.kind(), event.package) {
+            (Kind::Queued, _) => println!("job {}: queued", event.job),
+            (Kind::Unmerged, Some(pkg)) => println!("unmerged {pkg}: {files} file(s)"),
+            _ => (),
+        }
     }
     Ok(())
 }
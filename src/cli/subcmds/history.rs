@@ -0,0 +1,31 @@
+use anyhow::Result;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::argparse::positive_int;
+use arcanist::Client;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("history")
+        .about("query job history")
+        .long_about("List previously run jobs and their build results, most recent first.")
+        .disable_help_subcommand(true)
+        .arg(Arg::new("limit")
+            .takes_value(true)
+            .short('n')
+            .long("limit")
+            .value_name("COUNT")
+            .validator(positive_int)
+            .help("limit the number of jobs shown"))
+}
+
+pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
+    let limit = args.value_of("limit").map(|s| s.parse().unwrap());
+    for job in client.history(limit).await?.iter() {
+        println!("{job}");
+        for build in job.builds.iter() {
+            println!("  {build}");
+        }
+    }
+    Ok(())
+}
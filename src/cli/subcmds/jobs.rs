@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use clap::{Arg, ArgMatches, Command};
+
+use crate::argparse::positive_int;
+use arcanist::Client;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("jobs")
+        .about("query jobs")
+        .disable_help_subcommand(true)
+        .arg(Arg::new("jobs")
+            .takes_value(true)
+            .multiple_values(true)
+            .value_name("JOB")
+            .validator(positive_int)
+            .help("jobs to query"))
+}
+
+pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
+    match args.values_of("jobs") {
+        Some(ids) => {
+            for id in ids.map(|s| s.parse().unwrap()) {
+                let job = client
+                    .job(id)
+                    .await
+                    .context(format!("failed querying job: {id}"))?;
+                println!("{job}");
+            }
+        }
+        None => {
+            for job in client.jobs().await?.iter() {
+                println!("{job}");
+            }
+        }
+    }
+    Ok(())
+}
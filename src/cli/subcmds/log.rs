@@ -0,0 +1,48 @@
+use std::io::{self, Write};
+
+use anyhow::{Context, Result};
+use clap::{Arg, ArgMatches, Command};
+use futures::TryStreamExt;
+
+use crate::argparse::positive_int;
+use arcanist::Client;
+
+#[rustfmt::skip]
+pub fn cmd() -> Command<'static> {
+    Command::new("log")
+        .about("output job logs")
+        .disable_help_subcommand(true)
+        .arg(Arg::new("job")
+            .required(true)
+            .value_name("JOB")
+            .validator(positive_int)
+            .help("job to output the log for"))
+        .arg(Arg::new("follow")
+            .short('f')
+            .long("follow")
+            .help("output new log data until the job finishes"))
+        .arg(Arg::new("offset")
+            .takes_value(true)
+            .long("offset")
+            .value_name("BYTES")
+            .validator(|s| s.parse::<u64>())
+            .help("start from the given byte offset"))
+}
+
+pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
+    let id = args.value_of("job").unwrap().parse().unwrap();
+    let offset = args
+        .value_of("offset")
+        .map(|s| s.parse().unwrap())
+        .unwrap_or_default();
+    let mut stream = client
+        .log(id, offset, args.is_present("follow"))
+        .await
+        .context(format!("failed streaming job log: {id}"))?;
+    let mut stdout = io::stdout();
+    while let Some(chunk) = stream.try_next().await? {
+        stdout.write_all(&chunk.data)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
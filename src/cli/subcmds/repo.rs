@@ -2,7 +2,7 @@ use anyhow::Result;
 use clap::{ArgMatches, Command};
 
 use crate::settings::Settings;
-use crate::Client;
+use arcanist::Client;
 
 mod add;
 mod del;
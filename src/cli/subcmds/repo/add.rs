@@ -1,8 +1,7 @@
 use anyhow::{Context, Result};
 use clap::{Arg, ArgMatches, Command};
 
-use crate::Client;
-use arcanist::proto::AddRepoRequest;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -17,16 +16,12 @@ pub fn cmd() -> Command<'static> {
 }
 
 pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
-    let name = args.value_of("name").unwrap().to_string();
-    let uri = args.value_of("uri").unwrap().to_string();
-    let request = tonic::Request::new(AddRepoRequest {
-        name: name.clone(),
-        uri,
-    });
-    let response = client
-        .add_repo(request)
+    let name = args.value_of("name").unwrap();
+    let uri = args.value_of("uri").unwrap();
+    let repo = client
+        .add_repo(name, uri)
         .await
         .context(format!("failed adding repo: {name}"))?;
-    println!("{}", response.into_inner().data);
+    println!("{}", repo.id);
     Ok(())
 }
@@ -1,8 +1,7 @@
 use anyhow::{Context, Result};
 use clap::{Arg, ArgMatches, Command};
 
-use crate::Client;
-use arcanist::proto::ListRequest;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -17,14 +16,8 @@ pub fn cmd() -> Command<'static> {
 }
 
 pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
-    let repos: Vec<String> = args
-        .values_of("repos")
-        .unwrap()
-        .map(|s| s.to_string())
-        .collect();
-    let request = tonic::Request::new(ListRequest { data: repos });
     client
-        .remove_repos(request)
+        .remove_repos(args.values_of("repos").unwrap())
         .await
         .context("failed removing repo(s)")?;
     Ok(())
@@ -1,8 +1,7 @@
 use anyhow::Result;
 use clap::Command;
 
-use crate::Client;
-use arcanist::proto::StringRequest;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -13,12 +12,8 @@ pub fn cmd() -> Command<'static> {
 
 pub async fn run(client: &mut Client) -> Result<()> {
     // TODO: add support for specifying repo types
-    let request = tonic::Request::new(StringRequest {
-        data: "repos".to_string(),
-    });
-    let response = client.list_repos(request).await?;
-    for repo in response.into_inner().data.iter() {
-        println!("{}", repo);
+    for repo in client.repos().await?.iter() {
+        println!("{repo}");
     }
     Ok(())
 }
@@ -1,8 +1,7 @@
 use anyhow::{Context, Result};
 use clap::{Arg, ArgMatches, Command};
 
-use crate::Client;
-use arcanist::proto::StringRequest;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -14,10 +13,9 @@ pub fn cmd() -> Command<'static> {
 }
 
 pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
-    let name = args.value_of("name").unwrap().to_string();
-    let request = tonic::Request::new(StringRequest { data: name.clone() });
+    let name = args.value_of("name").unwrap();
     client
-        .create_repo(request)
+        .create_repo(name)
         .await
         .context(format!("failed creating repo: {name}"))?;
     Ok(())
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
 use clap::{Arg, ArgMatches, Command};
+use futures::TryStreamExt;
 
-use crate::Client;
-use arcanist::proto::ListRequest;
+use arcanist::proto::sync_event::Kind;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -16,16 +17,21 @@ pub fn cmd() -> Command<'static> {
 }
 
 pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
-    let repos: Vec<String> = args
-        .values_of("repos")
-        .map(|names| names.map(|s| s.to_string()).collect())
-        .unwrap_or_else(Vec::new);
+    let ids = args.values_of("repos").into_iter().flatten();
+    let mut stream = client.sync(ids).await.context("failed syncing repo(s)")?;
 
-    let request = tonic::Request::new(ListRequest { data: repos });
-    client
-        .sync_repos(request)
-        .await
-        .context("failed syncing repo(s)")?;
+    // failed syncs end the stream with an error after their per-repo events
+    while let Some(event) = stream.try_next().await.context("failed syncing repo(s)")? {
+        let (repo, msg) = (&event.repo, &event.message);
+        match event.kind() {
+            Kind::Queued => println!("job {}: queued", event.job),
+            Kind::Started => println!("syncing {repo}"),
+            Kind::Progress => println!("{repo}: {msg}"),
+            Kind::Finished => println!("synced {repo}"),
+            Kind::Failed => eprintln!("failed syncing {repo}: {msg}"),
+            Kind::Unspecified => (),
+        }
+    }
 
     Ok(())
 }
@@ -1,8 +1,8 @@
 use anyhow::Result;
 use clap::{Arg, ArgMatches, Command};
+use futures::TryStreamExt;
 
-use crate::Client;
-use arcanist::proto::ListRequest;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -10,6 +10,7 @@ pub fn cmd() -> Command<'static> {
         .about("search repos")
         .disable_help_subcommand(true)
         .arg(Arg::new("pkgs")
+            .required(true)
             .takes_value(true)
             .multiple_values(true)
             .value_name("TARGET")
@@ -17,16 +18,9 @@ pub fn cmd() -> Command<'static> {
 }
 
 pub async fn run(args: &ArgMatches, client: &mut Client) -> Result<()> {
-    let pkgs: Vec<String> = args
-        .values_of("pkgs")
-        .unwrap()
-        .map(|s| s.to_string())
-        .collect();
-    let request = tonic::Request::new(ListRequest { data: pkgs });
-    let response = client.search_packages(request).await?;
-    let mut stream = response.into_inner();
-    while let Some(response) = stream.message().await? {
-        println!("{}", response.data);
+    let mut stream = client.search(args.values_of("pkgs").unwrap()).await?;
+    while let Some(pkg) = stream.try_next().await? {
+        println!("{pkg}");
     }
     Ok(())
 }
@@ -1,8 +1,7 @@
 use anyhow::Result;
 use clap::Command;
 
-use crate::Client;
-use arcanist::proto::StringRequest;
+use arcanist::Client;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -13,8 +12,7 @@ pub fn cmd() -> Command<'static> {
 
 pub async fn run(client: &mut Client) -> Result<()> {
     let version = format!("{}-{}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
-    let request = tonic::Request::new(StringRequest { data: version });
-    let response = client.version(request).await?;
-    println!("{}", response.into_inner().data);
+    let server = client.version().await?;
+    println!("client: {version}, server: {server}");
     Ok(())
 }
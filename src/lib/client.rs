@@ -0,0 +1,316 @@
+use std::path::Path;
+use std::time::Duration;
+
+use futures::stream::{BoxStream, StreamExt};
+use tokio::net::UnixStream;
+use tokio::time::timeout;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint, Uri};
+use tonic::{Request, Status, Streaming};
+use tower::service_fn;
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+use crate::proto::{
+    arcanist_client::ArcanistClient, AddRepoRequest, BuildEvent, CreateRepoRequest, Job,
+    JobHistoryRequest, JobId, JobLogChunk, JobLogRequest, ListJobsRequest, ListReposRequest,
+    Package, PackageTargets, ReloadConfigRequest, Repo, RepoIds, ShutdownRequest, SyncEvent,
+    UnmergeEvent, VersionRequest,
+};
+use crate::utils::{connect_or_spawn, REQUEST_ID_KEY};
+
+/// Stream of messages returned by a request, ending early on failure.
+pub type ResponseStream<T> = BoxStream<'static, Result<T>>;
+
+/// Generated gRPC client for direct access to the RPC API.
+pub type RawClient<T = Channel> = ArcanistClient<T>;
+
+/// Settings used when connecting to arcanist.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Connection timeout in seconds, also used when waiting for spawned instances to start.
+    pub timeout: Option<u64>,
+    /// Seconds spawned instances wait without activity before exiting.
+    pub idle_timeout: Option<u64>,
+    /// TLS config used for https connections.
+    pub tls: Option<ClientTlsConfig>,
+    /// User agent sent with requests, also reported as the client version.
+    pub user_agent: String,
+    /// ID attached to all requests, defaulting to a random ID per request.
+    pub request_id: Option<String>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            timeout: Some(5),
+            idle_timeout: None,
+            tls: None,
+            user_agent: format!("arcanist-client-{}", env!("CARGO_PKG_VERSION")),
+            request_id: None,
+        }
+    }
+}
+
+// Interceptor attaching request IDs used to correlate requests in arcanist logs, generating a
+// new ID for each request if one isn't given.
+#[derive(Debug, Clone)]
+struct RequestId(Option<AsciiMetadataValue>);
+
+impl Interceptor for RequestId {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let id = match &self.0 {
+            Some(id) => id.clone(),
+            None => Uuid::new_v4()
+                .to_string()
+                .parse()
+                .expect("invalid request ID"),
+        };
+        request.metadata_mut().insert(REQUEST_ID_KEY, id);
+        Ok(request)
+    }
+}
+
+// Convert a response stream into one returning library errors.
+fn stream<T: Send + 'static>(stream: Streaming<T>) -> ResponseStream<T> {
+    stream.map(|r| r.map_err(Error::from)).boxed()
+}
+
+// Collect strings from an iterator of values.
+fn strings<I, S>(values: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    values.into_iter().map(|s| s.into()).collect()
+}
+
+/// Asynchronous client for arcanist.
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: ArcanistClient<InterceptedService<Channel, RequestId>>,
+    user_agent: String,
+    request_id: Option<String>,
+}
+
+impl Client {
+    /// Connect to arcanist at a given unix domain socket path or URL using the default config.
+    pub async fn connect<S: AsRef<str>>(url: S) -> Result<Self> {
+        Self::connect_with(url, &ClientConfig::default()).await
+    }
+
+    /// Connect to arcanist at a given unix domain socket path or URL.
+    pub async fn connect_with<S: AsRef<str>>(url: S, config: &ClientConfig) -> Result<Self> {
+        let url = url.as_ref().to_string();
+        let transport_error = |source| Error::Transport {
+            url: url.clone(),
+            source,
+        };
+
+        let channel = match Url::parse(&url) {
+            Err(_) => {
+                let path = url.clone();
+                let connect = Endpoint::from_static("http://[::]")
+                    .user_agent(config.user_agent.as_str())
+                    .map_err(transport_error)?
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        UnixStream::connect(path.clone())
+                    }));
+                let channel = match config.timeout.filter(|x| *x > 0) {
+                    Some(secs) => timeout(Duration::from_secs(secs), connect)
+                        .await
+                        .map_err(|_| Error::Connect(format!("timed out: {url}")))?,
+                    None => connect.await,
+                };
+                channel.map_err(transport_error)?
+            }
+            Ok(parsed) => {
+                let mut endpoint = Endpoint::from_shared(url.clone())
+                    .and_then(|e| e.user_agent(config.user_agent.as_str()))
+                    .map_err(transport_error)?;
+                if parsed.scheme() == "https" {
+                    let tls = config.tls.clone().unwrap_or_else(ClientTlsConfig::new);
+                    endpoint = endpoint.tls_config(tls).map_err(transport_error)?;
+                }
+                if let Some(secs) = config.timeout.filter(|x| *x > 0) {
+                    endpoint = endpoint.connect_timeout(Duration::from_secs(secs));
+                }
+                endpoint.connect().await.map_err(transport_error)?
+            }
+        };
+
+        let request_id = config.request_id.clone();
+        let value = match &request_id {
+            Some(id) => Some(
+                id.parse()
+                    .map_err(|_| Error::Connect(format!("invalid request ID: {id}")))?,
+            ),
+            None => None,
+        };
+
+        Ok(Client {
+            inner: ArcanistClient::with_interceptor(channel, RequestId(value)),
+            user_agent: config.user_agent.clone(),
+            request_id,
+        })
+    }
+
+    /// Connect to arcanist at a given unix domain socket path, spawning it if it isn't running.
+    pub async fn connect_or_spawn<P: AsRef<Path>>(path: P, config: &ClientConfig) -> Result<Self> {
+        let socket = connect_or_spawn(path, config.timeout, config.idle_timeout).await?;
+        Self::connect_with(socket, config).await
+    }
+
+    /// Return the ID attached to all requests sent by the client, if one was configured.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Return the version of the connected arcanist instance.
+    pub async fn version(&mut self) -> Result<String> {
+        let request = VersionRequest {
+            client: self.user_agent.clone(),
+        };
+        let response = self.inner.version(request).await?;
+        Ok(response.into_inner().server)
+    }
+
+    /// Request arcanist to shut down, optionally waiting for running jobs to finish.
+    pub async fn shutdown(&mut self, wait_for_jobs: bool) -> Result<()> {
+        let request = ShutdownRequest { wait_for_jobs };
+        self.inner.shutdown(request).await?;
+        Ok(())
+    }
+
+    /// Reload the arcanist config, returning the applied changes.
+    pub async fn reload(&mut self) -> Result<Vec<String>> {
+        let response = self.inner.reload_config(ReloadConfigRequest {}).await?;
+        Ok(response.into_inner().changes)
+    }
+
+    /// Return all configured repos.
+    pub async fn repos(&mut self) -> Result<Vec<Repo>> {
+        let response = self.inner.list_repos(ListReposRequest {}).await?;
+        Ok(response.into_inner().repos)
+    }
+
+    /// Add a repo from a given URI.
+    pub async fn add_repo<N, U>(&mut self, name: N, uri: U) -> Result<Repo>
+    where
+        N: Into<String>,
+        U: Into<String>,
+    {
+        let request = AddRepoRequest {
+            name: name.into(),
+            uri: uri.into(),
+        };
+        Ok(self.inner.add_repo(request).await?.into_inner())
+    }
+
+    /// Create a new, empty repo.
+    pub async fn create_repo<S: Into<String>>(&mut self, name: S) -> Result<Repo> {
+        let request = CreateRepoRequest { name: name.into() };
+        Ok(self.inner.create_repo(request).await?.into_inner())
+    }
+
+    /// Remove repos, returning the removed repo IDs.
+    pub async fn remove_repos<I, S>(&mut self, ids: I) -> Result<Vec<String>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let request = RepoIds { ids: strings(ids) };
+        Ok(self.inner.remove_repos(request).await?.into_inner().ids)
+    }
+
+    /// Sync repos, defaulting to all repos when none are given.
+    pub async fn sync<I, S>(&mut self, ids: I) -> Result<ResponseStream<SyncEvent>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let request = RepoIds { ids: strings(ids) };
+        Ok(stream(self.inner.sync_repos(request).await?.into_inner()))
+    }
+
+    /// Search for packages matching the given targets.
+    pub async fn search<I, S>(&mut self, targets: I) -> Result<ResponseStream<Package>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let request = PackageTargets {
+            targets: strings(targets),
+        };
+        Ok(stream(
+            self.inner.search_packages(request).await?.into_inner(),
+        ))
+    }
+
+    /// Build and install packages matching the given targets.
+    pub async fn add<I, S>(&mut self, targets: I) -> Result<ResponseStream<BuildEvent>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let request = PackageTargets {
+            targets: strings(targets),
+        };
+        Ok(stream(self.inner.add_packages(request).await?.into_inner()))
+    }
+
+    /// Remove installed packages matching the given targets.
+    pub async fn remove<I, S>(&mut self, targets: I) -> Result<ResponseStream<UnmergeEvent>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let request = PackageTargets {
+            targets: strings(targets),
+        };
+        Ok(stream(
+            self.inner.remove_packages(request).await?.into_inner(),
+        ))
+    }
+
+    /// Return all unfinished and recently finished jobs.
+    pub async fn jobs(&mut self) -> Result<Vec<Job>> {
+        let response = self.inner.list_jobs(ListJobsRequest {}).await?;
+        Ok(response.into_inner().jobs)
+    }
+
+    /// Return a given job.
+    pub async fn job(&mut self, id: u64) -> Result<Job> {
+        Ok(self.inner.get_job(JobId { id }).await?.into_inner())
+    }
+
+    /// Cancel a given job, returning its updated state.
+    pub async fn cancel(&mut self, id: u64) -> Result<Job> {
+        Ok(self.inner.cancel_job(JobId { id }).await?.into_inner())
+    }
+
+    /// Return finished jobs, most recent first, optionally limited to a given number.
+    pub async fn history(&mut self, limit: Option<u32>) -> Result<Vec<Job>> {
+        let request = JobHistoryRequest {
+            limit: limit.unwrap_or_default(),
+        };
+        Ok(self.inner.job_history(request).await?.into_inner().jobs)
+    }
+
+    /// Stream a job's log output from a given offset, optionally following it until the job
+    /// finishes.
+    pub async fn log(
+        &mut self,
+        id: u64,
+        offset: u64,
+        follow: bool,
+    ) -> Result<ResponseStream<JobLogChunk>> {
+        let request = JobLogRequest { id, offset, follow };
+        Ok(stream(
+            self.inner.stream_job_log(request).await?.into_inner(),
+        ))
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use prost::Message;
+use tonic::{Code, Status};
+
+mod rpc {
+    tonic::include_proto!("google.rpc");
+}
+
+/// Domain of the error details attached to arcanist statuses.
+pub const ERROR_DOMAIN: &str = "arcanist";
+
+// type URL of encoded google.rpc.ErrorInfo messages
+const ERROR_INFO_TYPE: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+
+/// Reasons for failed requests, attached to statuses as google.rpc.ErrorInfo details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// A package target couldn't be parsed.
+    InvalidTarget,
+    /// No installed packages match the targets being removed.
+    NotInstalled,
+    /// Packages being removed are required by other installed packages.
+    PackageRequired,
+    /// A repo isn't configured.
+    RepoNotFound,
+    /// A repo is already configured.
+    RepoExists,
+    /// Repo configuration couldn't be updated.
+    RepoConfig,
+    /// A job is unknown.
+    JobNotFound,
+    /// A job was cancelled.
+    JobCancelled,
+    /// A build job failed.
+    BuildFailed,
+    /// A package removal job failed.
+    RemoveFailed,
+    /// A repo sync job failed.
+    SyncFailed,
+    /// The client isn't allowed to call a method.
+    PermissionDenied,
+    /// The daemon isn't accepting new jobs.
+    ShuttingDown,
+    /// The daemon config couldn't be loaded.
+    InvalidConfig,
+    /// An unexpected error occurred.
+    Internal,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidTarget => "INVALID_TARGET",
+            Self::NotInstalled => "NOT_INSTALLED",
+            Self::PackageRequired => "PACKAGE_REQUIRED",
+            Self::RepoNotFound => "REPO_NOT_FOUND",
+            Self::RepoExists => "REPO_EXISTS",
+            Self::RepoConfig => "REPO_CONFIG",
+            Self::JobNotFound => "JOB_NOT_FOUND",
+            Self::JobCancelled => "JOB_CANCELLED",
+            Self::BuildFailed => "BUILD_FAILED",
+            Self::RemoveFailed => "REMOVE_FAILED",
+            Self::SyncFailed => "SYNC_FAILED",
+            Self::PermissionDenied => "PERMISSION_DENIED",
+            Self::ShuttingDown => "SHUTTING_DOWN",
+            Self::InvalidConfig => "INVALID_CONFIG",
+            Self::Internal => "INTERNAL",
+        }
+    }
+
+    /// Return the gRPC status code used for the reason.
+    pub fn code(&self) -> Code {
+        match self {
+            Self::InvalidTarget | Self::NotInstalled => Code::InvalidArgument,
+            Self::PackageRequired => Code::FailedPrecondition,
+            Self::RepoNotFound | Self::JobNotFound => Code::NotFound,
+            Self::RepoExists => Code::AlreadyExists,
+            Self::RepoConfig | Self::InvalidConfig => Code::FailedPrecondition,
+            Self::JobCancelled => Code::Cancelled,
+            Self::BuildFailed | Self::RemoveFailed | Self::SyncFailed => Code::Aborted,
+            Self::PermissionDenied => Code::PermissionDenied,
+            Self::ShuttingDown => Code::Unavailable,
+            Self::Internal => Code::Internal,
+        }
+    }
+
+    /// Create a status for the reason.
+    pub fn status<S: Into<String>>(self, message: S) -> Status {
+        ErrorDetails::new(self).status(message)
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Reason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let reason = match s {
+            "INVALID_TARGET" => Self::InvalidTarget,
+            "NOT_INSTALLED" => Self::NotInstalled,
+            "PACKAGE_REQUIRED" => Self::PackageRequired,
+            "REPO_NOT_FOUND" => Self::RepoNotFound,
+            "REPO_EXISTS" => Self::RepoExists,
+            "REPO_CONFIG" => Self::RepoConfig,
+            "JOB_NOT_FOUND" => Self::JobNotFound,
+            "JOB_CANCELLED" => Self::JobCancelled,
+            "BUILD_FAILED" => Self::BuildFailed,
+            "REMOVE_FAILED" => Self::RemoveFailed,
+            "SYNC_FAILED" => Self::SyncFailed,
+            "PERMISSION_DENIED" => Self::PermissionDenied,
+            "SHUTTING_DOWN" => Self::ShuttingDown,
+            "INVALID_CONFIG" => Self::InvalidConfig,
+            "INTERNAL" => Self::Internal,
+            _ => return Err(format!("unknown error reason: {s}")),
+        };
+        Ok(reason)
+    }
+}
+
+/// Structured details of a failed request.
+///
+/// Metadata holds related values such as the ID of a failed job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDetails {
+    pub reason: Reason,
+    pub metadata: HashMap<String, String>,
+}
+
+impl ErrorDetails {
+    pub fn new(reason: Reason) -> Self {
+        ErrorDetails {
+            reason,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Add a metadata value to the details.
+    pub fn with<K: ToString, V: ToString>(mut self, key: K, value: V) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Create a status with the details attached.
+    pub fn status<S: Into<String>>(self, message: S) -> Status {
+        let (code, message) = (self.reason.code(), message.into());
+        let info = rpc::ErrorInfo {
+            reason: self.reason.as_str().to_string(),
+            domain: ERROR_DOMAIN.to_string(),
+            metadata: self.metadata,
+        };
+        let details = rpc::Status {
+            code: code as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: ERROR_INFO_TYPE.to_string(),
+                value: info.encode_to_vec(),
+            }],
+        };
+        Status::with_details(code, message, details.encode_to_vec().into())
+    }
+
+    /// Extract the details attached to a status, if any.
+    ///
+    /// Details from other domains or with unknown reasons are ignored.
+    pub fn from_status(status: &Status) -> Option<Self> {
+        let details = rpc::Status::decode(status.details()).ok()?;
+        details
+            .details
+            .iter()
+            .filter(|any| any.type_url == ERROR_INFO_TYPE)
+            .filter_map(|any| rpc::ErrorInfo::decode(any.value.as_slice()).ok())
+            .filter(|info| info.domain == ERROR_DOMAIN)
+            .find_map(|info| {
+                let reason = info.reason.parse().ok()?;
+                Some(ErrorDetails {
+                    reason,
+                    metadata: info.metadata,
+                })
+            })
+    }
+}
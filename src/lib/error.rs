@@ -1,3 +1,5 @@
+use crate::ErrorDetails;
+
 /// A `Result` alias where the `Err` case is `arcanist::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -5,6 +7,34 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[error("failed connecting to arcanist: {0}")]
     Connect(String),
+    #[error("failed connecting to arcanist: {url}")]
+    Transport {
+        url: String,
+        source: tonic::transport::Error,
+    },
     #[error("failed starting arcanist: {0}")]
     Start(String),
+    #[error("{}", .0.message())]
+    Status(tonic::Status),
+}
+
+impl Error {
+    /// Return the status of a failed request.
+    pub fn status(&self) -> Option<&tonic::Status> {
+        match self {
+            Self::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Return the structured details of a failed request, if any.
+    pub fn details(&self) -> Option<ErrorDetails> {
+        self.status().and_then(ErrorDetails::from_status)
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::Status(status)
+    }
 }
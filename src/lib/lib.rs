@@ -1,12 +1,12 @@
+mod client;
+mod details;
 mod error;
+pub mod proto;
 mod utils;
 
-pub mod proto {
-    tonic::include_proto!("arcanist");
-}
-
-pub use self::proto::arcanist_client::ArcanistClient as Client;
 pub use self::proto::arcanist_server::ArcanistServer as Server;
 
+pub use self::client::{Client, ClientConfig, RawClient, ResponseStream};
+pub use self::details::{ErrorDetails, Reason, ERROR_DOMAIN};
 pub use self::error::{Error, Result};
-pub use self::utils::{connect_or_spawn, spawn};
+pub use self::utils::{connect_or_spawn, spawn, ReadyInfo, API_VERSION, REQUEST_ID_KEY};
@@ -0,0 +1,74 @@
+use std::fmt;
+
+tonic::include_proto!("arcanist");
+
+/// Encoded file descriptor set for the arcanist protocol, used for gRPC reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("arcanist_descriptor");
+
+impl fmt::Display for Repo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.id, self.path)
+    }
+}
+
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.category, self.name)?;
+        if !self.version.is_empty() {
+            write!(f, "-{}", self.version)?;
+        }
+        if !self.slot.is_empty() {
+            write!(f, ":{}", self.slot)?;
+        }
+        write!(f, "::{}", self.repo)
+    }
+}
+
+impl fmt::Display for job::Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Build => "build",
+            Self::Sync => "sync",
+            Self::Remove => "remove",
+            Self::Unspecified => "unknown",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl fmt::Display for job::State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+            Self::Unspecified => "unknown",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.id, self.kind(), self.state())?;
+        if !self.targets.is_empty() {
+            write!(f, " {}", self.targets.join(" "))?;
+        }
+        if !self.error.is_empty() {
+            write!(f, " ({})", self.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BuildResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self.error.is_empty() {
+            true => "succeeded",
+            false => "failed",
+        };
+        write!(f, "{}: {outcome} in {:.1}s", self.package, self.duration)
+    }
+}
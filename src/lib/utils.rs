@@ -1,31 +1,60 @@
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
-use std::os::unix::net::UnixStream;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::Path;
 use std::process::Stdio;
 use std::time::Duration;
 
-use once_cell::sync::Lazy;
-use regex::Regex;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::unistd::pipe2;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    io::AsyncBufReadExt,
-    io::BufReader,
+    fs::File,
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::UnixStream,
     process::{Child, Command},
-    time::timeout as timeout_future,
+    time::{sleep, timeout as timeout_future},
 };
+use tonic::transport::{Endpoint, Uri};
+use tonic_health::proto::health_check_response::ServingStatus;
+use tonic_health::proto::{health_client::HealthClient, HealthCheckRequest};
+use tower::service_fn;
 
 use crate::error::Error;
 
-static ARCANIST_RE: Lazy<Regex> =
-    Lazy::new(|| Regex::new("^arcanist listening at: (?P<socket>.+)$").unwrap());
+/// Version of the RPC API reported by arcanist on startup.
+pub const API_VERSION: u32 = 1;
 
+/// Metadata key used to pass request IDs between clients and arcanist.
+pub const REQUEST_ID_KEY: &str = "x-request-id";
+
+// name of the arcanist gRPC service used for health checks
+const SERVICE_NAME: &str = "arcanist.Arcanist";
+
+/// Startup info reported by arcanist once it's ready to accept connections.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReadyInfo {
+    /// Sockets arcanist is listening on.
+    pub sockets: Vec<String>,
+    pub pid: u32,
+    pub version: String,
+    pub api_version: u32,
+    /// Address metrics are served on, if enabled.
+    #[serde(default)]
+    pub metrics: Option<String>,
+}
+
+/// Spawn arcanist bound to a given socket, waiting for it to report it's ready.
+///
+/// Output written to stderr before arcanist is ready is used to report startup failures while
+/// later output is discarded.
 pub async fn spawn<S, I, A, O>(
     socket: S,
     env: Option<I>,
     args: Option<A>,
     timeout: Option<u64>,
-) -> crate::Result<(Child, String)>
+) -> crate::Result<(Child, ReadyInfo)>
 where
     S: AsRef<str>,
     I: IntoIterator<Item = (O, O)>,
@@ -47,50 +76,116 @@ where
         cmd.args(args);
     }
 
+    // create a pipe for arcanist to report its startup info on, only inheriting the write end
+    let (ready_r, ready_w) = pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Start(e.to_string()))?;
+    let ready = unsafe { fs::File::from_raw_fd(ready_r) };
+    let ready_w = unsafe { fs::File::from_raw_fd(ready_w) };
+
+    // the write end is only made inheritable in the child so processes spawned concurrently by
+    // other threads can't hold it open
+    let fd = ready_w.as_raw_fd();
+    unsafe {
+        cmd.pre_exec(move || {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            Ok(())
+        });
+    }
+
     // start arcanist detached from the current process while capturing stderr
     let mut arcanist = cmd
         .args(&["--bind", socket.as_ref()])
+        .args(&["--ready-fd", &ready_w.as_raw_fd().to_string()])
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::piped())
         .spawn()
         .map_err(|e| Error::Start(e.to_string()))?;
 
+    // close the write end so reads fail if arcanist exits without reporting
+    drop(ready_w);
+
     // wait for arcanist to report it's running
-    let stderr = arcanist.stderr.take().expect("no stderr");
-    let f = BufReader::new(stderr);
-    let socket = match timeout_future(timeout, f.lines().next_line()).await {
-        Ok(Ok(Some(line))) => {
-            match ARCANIST_RE.captures(&line) {
-                Some(m) => Ok(m.name("socket").unwrap().as_str().to_owned()),
-                None => {
-                    // try to kill arcanist, but ignore failures
-                    arcanist.kill().await.ok();
-                    Err(Error::Start(format!("unknown arcanist message: {line}")))
-                }
+    let mut f = BufReader::new(File::from_std(ready));
+    let mut line = String::new();
+    let info = match timeout_future(timeout, f.read_line(&mut line)).await {
+        Ok(Ok(0)) => {
+            // arcanist exited before becoming ready, try to report why
+            let mut msg = String::new();
+            if let Some(mut stderr) = arcanist.stderr.take() {
+                stderr.read_to_string(&mut msg).await.ok();
+            }
+            let msg = msg.trim();
+            match msg.is_empty() {
+                true => Err(Error::Start("no startup info received".to_string())),
+                false => Err(Error::Start(msg.to_string())),
             }
         }
-        Err(_) => {
-            arcanist.kill().await.ok();
-            Err(Error::Start("timed out".to_string()))
-        }
-        Ok(Err(e)) => {
-            // unknown IO error
-            arcanist.kill().await.ok();
-            Err(Error::Start(e.to_string()))
-        }
-        Ok(Ok(None)) => {
-            arcanist.kill().await.ok();
-            Err(Error::Start("no startup message found".to_string()))
-        }
+        Ok(Ok(_)) => serde_json::from_str(&line)
+            .map_err(|e| Error::Start(format!("invalid startup info: {e}: {}", line.trim()))),
+        Ok(Err(e)) => Err(Error::Start(e.to_string())),
+        Err(_) => Err(Error::Start("timed out".to_string())),
+    };
+
+    if info.is_err() {
+        // try to kill arcanist, but ignore failures
+        arcanist.kill().await.ok();
+    } else if let Some(mut stderr) = arcanist.stderr.take() {
+        // keep draining stderr so logging to it can't block arcanist once the pipe fills
+        tokio::spawn(async move { tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await });
+    }
+
+    Ok((arcanist, info?))
+}
+
+// Query the serving status of arcanist over an established unix domain socket connection.
+async fn health_check(stream: UnixStream) -> crate::Result<ServingStatus> {
+    // the connection is only used once so reconnection attempts fail
+    let mut stream = Some(stream);
+    let connector = service_fn(move |_: Uri| {
+        let stream = stream.take();
+        async move { stream.ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected)) }
+    });
+    let channel = Endpoint::from_static("http://[::]")
+        .connect_with_connector(connector)
+        .await
+        .map_err(|e| Error::Connect(e.to_string()))?;
+
+    let request = HealthCheckRequest {
+        service: SERVICE_NAME.to_string(),
     };
+    let response = HealthClient::new(channel)
+        .check(request)
+        .await
+        .map_err(|e| Error::Connect(format!("health check failed: {}", e.message())))?;
+    Ok(response.into_inner().status())
+}
 
-    Ok((arcanist, socket?))
+// Wait for arcanist to report it's serving, e.g. while reloading its config, starting with an
+// established connection and reconnecting for each following health check.
+async fn wait_serving(stream: UnixStream, path: &Path) -> crate::Result<()> {
+    let mut stream = Some(stream);
+    loop {
+        let conn = match stream.take() {
+            Some(conn) => conn,
+            None => UnixStream::connect(path)
+                .await
+                .map_err(|e| Error::Connect(format!("{e}: {path:?}")))?,
+        };
+        if health_check(conn).await? == ServingStatus::Serving {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
 }
 
+/// Connect to arcanist at a given unix domain socket path, spawning it if it isn't running.
+///
+/// Running instances that aren't serving yet are waited on until the timeout expires. Spawned
+/// instances exit after being idle for the given number of seconds, if specified.
 pub async fn connect_or_spawn<P: AsRef<Path>>(
     path: P,
     timeout: Option<u64>,
+    idle_timeout: Option<u64>,
 ) -> crate::Result<String> {
     let socket_path = path.as_ref();
     let socket = socket_path
@@ -98,19 +193,37 @@ pub async fn connect_or_spawn<P: AsRef<Path>>(
         .ok_or_else(|| Error::Connect(format!("invalid socket path: {socket_path:?}")))?
         .to_string();
 
-    if let Err(e) = UnixStream::connect(&socket_path) {
-        match e.kind() {
+    // zero or an unset value effectively means no timeout occurs
+    let check_timeout = match timeout {
+        None | Some(0) => Duration::from_secs(u64::MAX),
+        Some(x) => Duration::from_secs(x),
+    };
+
+    match UnixStream::connect(&socket_path).await {
+        Ok(stream) => {
+            match timeout_future(check_timeout, wait_serving(stream, socket_path)).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => {
+                    return Err(Error::Connect(format!(
+                        "arcanist not serving before timeout: {socket}"
+                    )))
+                }
+            }
+        }
+        Err(e) => match e.kind() {
             // spawn arcanist if it's not running
             io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound => {
                 // remove potentially existing, old socket file
                 fs::remove_file(&socket_path).unwrap_or_default();
                 // spawn arcanist and wait for it to start
-                let env: Option<Vec<(&str, &str)>> = None;
-                let args: Option<Vec<&str>> = None;
+                let env: Option<Vec<(String, String)>> = None;
+                let args =
+                    idle_timeout.map(|secs| vec!["--idle-timeout".to_string(), secs.to_string()]);
                 spawn(&socket, env, args, timeout).await?;
             }
             _ => return Err(Error::Connect(format!("{e}: {socket_path:?}"))),
-        }
+        },
     }
 
     Ok(socket)
@@ -0,0 +1,325 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{Context as AnyhowContext, Result};
+use arcanist::{ErrorDetails, Reason};
+use futures::future::BoxFuture;
+use nix::unistd::{getuid, Gid, Group, Uid, User};
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Extensions, Request, Response};
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::transport::Body;
+use tower::{Layer, Service};
+
+use crate::settings::Access;
+use crate::uds::UdsConnectInfo;
+
+// RPC methods that don't modify daemon state, all others require admin access
+const READ_ONLY_METHODS: &[&str] = &[
+    "/grpc.health.v1.Health/Check",
+    "/grpc.health.v1.Health/Watch",
+    "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+    "/arcanist.Arcanist/Version",
+    "/arcanist.Arcanist/ListRepos",
+    "/arcanist.Arcanist/SearchPackages",
+    "/arcanist.Arcanist/ListJobs",
+    "/arcanist.Arcanist/GetJob",
+    "/arcanist.Arcanist/StreamJobLog",
+    "/arcanist.Arcanist/JobHistory",
+];
+
+/// Access level granted to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    ReadOnly,
+    Admin,
+}
+
+/// Policy mapping clients to roles.
+///
+/// Using peer access, local clients running as root, the daemon's user, or members of the
+/// admin group are granted admin access as are network clients verified via TLS client
+/// certificates. All other clients are read-only.
+#[derive(Debug)]
+pub struct Policy {
+    access: Access,
+    uid: Uid,
+    admin_group: Option<Group>,
+}
+
+impl Policy {
+    pub fn new(access: Access, admin_group: &str) -> Result<Self> {
+        let admin_group = match admin_group.is_empty() {
+            true => None,
+            false => {
+                let group = Group::from_name(admin_group)
+                    .context(format!("failed looking up group: {admin_group}"))?
+                    .context(format!("unknown group: {admin_group}"))?;
+                Some(group)
+            }
+        };
+
+        Ok(Policy {
+            access,
+            uid: getuid(),
+            admin_group,
+        })
+    }
+
+    // Determine the role for a local client.
+    fn uds_role(&self, uid: Uid, gid: Gid) -> Role {
+        if uid.is_root() || uid == self.uid {
+            return Role::Admin;
+        }
+
+        if let Some(group) = &self.admin_group {
+            if gid == group.gid {
+                return Role::Admin;
+            }
+            // check supplementary group membership
+            if let Ok(Some(user)) = User::from_uid(uid) {
+                if group.mem.contains(&user.name) {
+                    return Role::Admin;
+                }
+            }
+        }
+
+        Role::ReadOnly
+    }
+
+    // Determine the role for a client.
+    fn peer_role(&self, peer: &Peer) -> Role {
+        match self.access {
+            Access::Peer => (),
+            Access::ReadOnly => return Role::ReadOnly,
+            Access::Trusted => return Role::Admin,
+        }
+
+        match peer {
+            Peer::Local(Some((uid, gid))) => self.uds_role(*uid, *gid),
+            Peer::Local(None) => Role::ReadOnly,
+            Peer::Network { verified: true } => Role::Admin,
+            Peer::Network { verified: false } => Role::ReadOnly,
+        }
+    }
+
+    /// Determine the role for a client using its connection info.
+    pub fn role(&self, extensions: &Extensions) -> Role {
+        self.peer_role(&Peer::new(extensions))
+    }
+
+    /// Determine if a client is permitted to call the method at a given request path.
+    pub fn permitted(&self, path: &str, extensions: &Extensions) -> bool {
+        READ_ONLY_METHODS.contains(&path) || self.role(extensions) == Role::Admin
+    }
+}
+
+// Client identity determined from its connection info.
+#[derive(Debug)]
+enum Peer {
+    // local client with its user and group IDs, if available
+    Local(Option<(Uid, Gid)>),
+    // network client, noting whether it presented a verified client certificate
+    Network { verified: bool },
+}
+
+impl Peer {
+    fn new(extensions: &Extensions) -> Self {
+        if let Some(info) = extensions.get::<UdsConnectInfo>() {
+            let ids = info
+                .peer_cred
+                .map(|cred| (Uid::from_raw(cred.uid()), Gid::from_raw(cred.gid())));
+            return Peer::Local(ids);
+        }
+
+        // client certificates are only accepted if signed by the configured client CA
+        let tls = extensions.get::<TlsConnectInfo<TcpConnectInfo>>();
+        Peer::Network {
+            verified: tls.and_then(|info| info.peer_certs()).is_some(),
+        }
+    }
+}
+
+/// Layer rejecting requests to admin methods from clients without admin access.
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    policy: Arc<Policy>,
+}
+
+impl AuthLayer {
+    pub fn new(policy: Policy) -> Self {
+        AuthLayer {
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Auth<S> {
+    inner: S,
+    policy: Arc<Policy>,
+}
+
+impl<S> Service<Request<Body>> for Auth<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // gRPC request paths are in the form of /{package}.{service}/{method}
+        let path = req.uri().path();
+        let method = path.rsplit('/').next().unwrap_or_default();
+        if !self.policy.permitted(path, req.extensions()) {
+            let status = ErrorDetails::new(Reason::PermissionDenied)
+                .with("method", method)
+                .status(format!("{method} not permitted"));
+            return Box::pin(async move { Ok(status.to_http()) });
+        }
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    fn policy_for(access: Access, admin_group: Option<Group>) -> Policy {
+        Policy {
+            access,
+            uid: Uid::from_raw(1000),
+            admin_group,
+        }
+    }
+
+    fn group(gid: u32, members: &[&str]) -> Group {
+        Group {
+            name: "arcanist".to_string(),
+            passwd: CString::new("x").unwrap(),
+            gid: Gid::from_raw(gid),
+            mem: members.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn local(uid: u32, gid: u32) -> Peer {
+        Peer::Local(Some((Uid::from_raw(uid), Gid::from_raw(gid))))
+    }
+
+    #[test]
+    fn test_peer_role() {
+        let policy = policy_for(Access::Peer, None);
+
+        // root and the daemon's user are admins
+        assert_eq!(policy.peer_role(&local(0, 0)), Role::Admin);
+        assert_eq!(policy.peer_role(&local(1000, 1000)), Role::Admin);
+        // other local users are read-only
+        assert_eq!(policy.peer_role(&local(1001, 1000)), Role::ReadOnly);
+        assert_eq!(policy.peer_role(&Peer::Local(None)), Role::ReadOnly);
+
+        // network clients require verified client certificates
+        let verified = Peer::Network { verified: true };
+        let unverified = Peer::Network { verified: false };
+        assert_eq!(policy.peer_role(&verified), Role::Admin);
+        assert_eq!(policy.peer_role(&unverified), Role::ReadOnly);
+    }
+
+    #[test]
+    fn test_admin_group() {
+        // primary group membership
+        let policy = policy_for(Access::Peer, Some(group(2000, &[])));
+        assert_eq!(policy.peer_role(&local(1001, 2000)), Role::Admin);
+        assert_eq!(policy.peer_role(&local(1001, 1001)), Role::ReadOnly);
+
+        // supplementary group membership
+        if let Ok(Some(user)) = User::from_name("nobody") {
+            let peer = Peer::Local(Some((user.uid, user.gid)));
+            let policy = policy_for(Access::Peer, Some(group(2000, &["nobody"])));
+            assert_eq!(policy.peer_role(&peer), Role::Admin);
+            let policy = policy_for(Access::Peer, Some(group(2000, &["somebody"])));
+            assert_eq!(policy.peer_role(&peer), Role::ReadOnly);
+        }
+    }
+
+    #[test]
+    fn test_access() {
+        let verified = Peer::Network { verified: true };
+        let unverified = Peer::Network { verified: false };
+
+        // read-only access overrides peer identity
+        let policy = policy_for(Access::ReadOnly, None);
+        assert_eq!(policy.peer_role(&local(0, 0)), Role::ReadOnly);
+        assert_eq!(policy.peer_role(&verified), Role::ReadOnly);
+
+        // trusted access grants all clients admin access
+        let policy = policy_for(Access::Trusted, None);
+        assert_eq!(policy.peer_role(&local(1001, 1001)), Role::Admin);
+        assert_eq!(policy.peer_role(&unverified), Role::Admin);
+    }
+
+    #[tokio::test]
+    async fn test_role() {
+        let policy = Policy::new(Access::Peer, "").unwrap();
+
+        // local clients are identified by their peer credentials
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        let mut extensions = Extensions::new();
+        extensions.insert(UdsConnectInfo {
+            peer_addr: None,
+            peer_cred: stream.peer_cred().ok(),
+        });
+        assert_eq!(policy.role(&extensions), Role::Admin);
+
+        // network clients without client certificates are read-only
+        assert_eq!(policy.role(&Extensions::new()), Role::ReadOnly);
+    }
+
+    #[test]
+    fn test_permitted() {
+        let policy = Policy::new(Access::ReadOnly, "").unwrap();
+        let extensions = Extensions::new();
+
+        // read-only clients can check health, use reflection, and query state
+        for path in [
+            "/grpc.health.v1.Health/Check",
+            "/grpc.health.v1.Health/Watch",
+            "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+            "/arcanist.Arcanist/ListRepos",
+        ] {
+            assert!(policy.permitted(path, &extensions), "{path} not permitted");
+        }
+
+        // but not modify it or call unknown methods
+        for path in [
+            "/arcanist.Arcanist/AddPackages",
+            "/arcanist.Arcanist/Unknown",
+        ] {
+            assert!(!policy.permitted(path, &extensions), "{path} permitted");
+        }
+
+        // admins can call anything
+        let policy = Policy::new(Access::Trusted, "").unwrap();
+        assert!(policy.permitted("/arcanist.Arcanist/AddPackages", &extensions));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use pkgcraft::atom::Atom;
+use pkgcraft::config::Config as PkgcraftConfig;
+use pkgcraft::pkg::ebuild::Pkg;
+use pkgcraft::repo::Repository;
+use pkgcraft::restrict::{self, Restrict, Restriction};
+
+use crate::installed::Database;
+
+/// Return the best matching package for a restriction across all ebuild repos.
+pub(crate) fn best_match<'a>(config: &'a PkgcraftConfig, restrict: &Restrict) -> Option<Pkg<'a>> {
+    config
+        .repos
+        .iter()
+        .filter_map(|(_, repo)| repo.as_ebuild())
+        .flat_map(|repo| repo.iter_restrict(restrict.clone()))
+        .max()
+}
+
+/// Return the dependencies required to build and run a package.
+pub(crate) fn pkg_deps(pkg: &Pkg) -> Vec<Atom> {
+    [pkg.bdepend(), pkg.depend(), pkg.rdepend()]
+        .into_iter()
+        .flatten()
+        .flat_map(|deps| deps.flatten())
+        .filter(|a| a.blocker().is_none())
+        .cloned()
+        .collect()
+}
+
+/// Resolve the given targets to their best matching packages, returning them and their
+/// dependencies in build order.
+///
+/// Targets are always rebuilt while dependencies satisfied by installed packages are skipped.
+pub(crate) fn resolve<'a, S: AsRef<str>>(
+    config: &'a PkgcraftConfig,
+    db: &Database,
+    targets: &[S],
+) -> Result<Vec<Pkg<'a>>> {
+    let mut pkgs = Vec::new();
+    for target in targets {
+        let target = target.as_ref();
+        let restrict = restrict::parse::dep(target).map_err(|e| anyhow!("{e}"))?;
+        match best_match(config, &restrict) {
+            Some(pkg) => pkgs.push(pkg),
+            None => bail!("no matches found: {target}"),
+        }
+    }
+
+    let installed = db
+        .pkgs()?
+        .iter()
+        .map(|p| p.atom())
+        .collect::<Result<Vec<_>>>()?;
+
+    build_order(
+        pkgs,
+        |pkg| pkg.to_string(),
+        |pkg| {
+            let mut deps = Vec::new();
+            for dep in pkg_deps(pkg) {
+                let restrict = Restrict::from(&dep);
+                if installed.iter().any(|a| restrict.matches(a)) {
+                    continue;
+                }
+                match best_match(config, &restrict) {
+                    Some(dep_pkg) => deps.push(dep_pkg),
+                    None => bail!("{pkg}: unresolvable dependency: {dep}"),
+                }
+            }
+            Ok(deps)
+        },
+    )
+}
+
+/// Order packages so each one follows its dependencies, identifying packages by the given key.
+fn build_order<T, K, D>(pkgs: Vec<T>, key: K, mut deps: D) -> Result<Vec<T>>
+where
+    K: Fn(&T) -> String,
+    D: FnMut(&T) -> Result<Vec<T>>,
+{
+    let mut ordered = Vec::new();
+    let mut seen = HashSet::new();
+    for pkg in pkgs {
+        add_pkg(pkg, &key, &mut deps, &mut seen, &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+/// Add a package to the build list after recursively adding its dependencies.
+fn add_pkg<T, K, D>(
+    pkg: T,
+    key: &K,
+    deps: &mut D,
+    seen: &mut HashSet<String>,
+    pkgs: &mut Vec<T>,
+) -> Result<()>
+where
+    K: Fn(&T) -> String,
+    D: FnMut(&T) -> Result<Vec<T>>,
+{
+    // dependency cycles are broken by marking packages before descending
+    if !seen.insert(key(&pkg)) {
+        return Ok(());
+    }
+
+    for dep in deps(&pkg)? {
+        add_pkg(dep, key, deps, seen, pkgs)?;
+    }
+
+    pkgs.push(pkg);
+    Ok(())
+}
+
+/// Merge an image directory into the given root, returning the installed files.
+pub(crate) fn merge<P: AsRef<Path>, Q: AsRef<Path>>(image: P, root: Q) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    merge_dir(image.as_ref(), image.as_ref(), root.as_ref(), &mut files)
+        .context(format!("failed merging to root: {:?}", root.as_ref()))?;
+    Ok(files)
+}
+
+fn merge_dir(image: &Path, dir: &Path, root: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let rel_path = path.strip_prefix(image).expect("invalid image path");
+        let target = root.join(rel_path);
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+            merge_dir(image, &path, root, files)?;
+        } else {
+            // replace existing files
+            fs::remove_file(&target).unwrap_or_default();
+            if file_type.is_symlink() {
+                symlink(fs::read_link(&path)?, &target)?;
+            } else {
+                fs::copy(&path, &target)?;
+            }
+            files.push(Path::new("/").join(rel_path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // Order packages using a static dependency graph.
+    fn order(targets: &[&str], graph: &[(&str, &[&str])]) -> Vec<String> {
+        let graph: HashMap<_, _> = graph.iter().cloned().collect();
+        let targets = targets.iter().map(|s| s.to_string()).collect();
+        build_order(
+            targets,
+            |pkg| pkg.clone(),
+            |pkg| {
+                let deps = graph.get(pkg.as_str()).copied().unwrap_or_default();
+                Ok(deps.iter().map(|s| s.to_string()).collect())
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_build_order() {
+        // no deps
+        assert_eq!(order(&["a", "b"], &[]), ["a", "b"]);
+
+        // deps come before their dependents
+        let graph: &[(&str, &[&str])] = &[("a", &["b", "c"]), ("b", &["c"])];
+        assert_eq!(order(&["a"], graph), ["c", "b", "a"]);
+
+        // packages are only built once
+        let graph: &[(&str, &[&str])] = &[("a", &["c"]), ("b", &["c"])];
+        assert_eq!(order(&["a", "b"], graph), ["c", "a", "b"]);
+        assert_eq!(order(&["a", "c"], graph), ["c", "a"]);
+    }
+
+    #[test]
+    fn test_build_order_cycles() {
+        // self dependency
+        let graph: &[(&str, &[&str])] = &[("a", &["a"])];
+        assert_eq!(order(&["a"], graph), ["a"]);
+
+        // cycles are broken at the first package revisited
+        let graph: &[(&str, &[&str])] = &[("a", &["b"]), ("b", &["c"]), ("c", &["a"])];
+        assert_eq!(order(&["a"], graph), ["c", "b", "a"]);
+        assert_eq!(order(&["b"], graph), ["a", "c", "b"]);
+
+        // packages depending on a cycle follow it
+        let graph: &[(&str, &[&str])] = &[("a", &["b"]), ("b", &["a"]), ("c", &["a"])];
+        assert_eq!(order(&["c"], graph), ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn test_build_order_errors() {
+        let result = build_order(
+            vec!["a".to_string()],
+            |pkg| pkg.clone(),
+            |pkg| bail!("{pkg}: unresolvable dependency: b"),
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "a: unresolvable dependency: b"
+        );
+    }
+}
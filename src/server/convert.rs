@@ -0,0 +1,103 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use arcanist::proto::{self, job::Kind, job::State};
+use arcanist::Reason;
+use pkgcraft::pkg::{ebuild::Pkg, Package};
+use pkgcraft::repo::{Repo, Repository};
+use tonic::Status;
+
+use crate::installed::InstalledPkg;
+use crate::jobs::{BuildRecord, Job, JobKind, JobState};
+
+/// Convert a configured repo into its protobuf representation.
+pub(crate) fn repo(id: &str, repo: &Repo) -> proto::Repo {
+    let config = repo.repo_config();
+    proto::Repo {
+        id: id.to_string(),
+        path: repo.path().to_string(),
+        priority: config.priority,
+        format: config.format.to_string(),
+        sync_uri: config
+            .sync
+            .as_ref()
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Convert an ebuild package into its protobuf representation.
+pub(crate) fn pkg(pkg: &Pkg) -> proto::Package {
+    let atom = pkg.atom();
+    proto::Package {
+        category: atom.category().to_string(),
+        name: atom.package().to_string(),
+        version: atom.version().map(|v| v.to_string()).unwrap_or_default(),
+        slot: pkg.slot().to_string(),
+        repo: pkg.repo().id().to_string(),
+    }
+}
+
+/// Convert an installed package into its protobuf representation.
+pub(crate) fn installed_pkg(pkg: &InstalledPkg) -> Result<proto::Package, Status> {
+    let atom = pkg
+        .atom()
+        .map_err(|e| Reason::Internal.status(format!("{e:#}")))?;
+    Ok(proto::Package {
+        category: atom.category().to_string(),
+        name: atom.package().to_string(),
+        version: atom.version().map(|v| v.to_string()).unwrap_or_default(),
+        slot: pkg.slot.clone(),
+        repo: pkg.repo.clone(),
+    })
+}
+
+fn timestamp(time: Option<SystemTime>) -> i64 {
+    time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Convert a package build outcome into its protobuf representation.
+pub(crate) fn build_record(record: &BuildRecord) -> proto::BuildResult {
+    proto::BuildResult {
+        package: record.pkg.clone(),
+        error: record.error.clone().unwrap_or_default(),
+        started: timestamp(Some(record.started)),
+        duration: record.duration.as_secs_f64(),
+        files: record
+            .files
+            .iter()
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect(),
+    }
+}
+
+/// Convert a job status snapshot into its protobuf representation.
+pub(crate) fn job(job: &Job) -> proto::Job {
+    let kind = match job.kind {
+        JobKind::Build => Kind::Build,
+        JobKind::Sync => Kind::Sync,
+        JobKind::Remove => Kind::Remove,
+    };
+    let state = match job.state {
+        JobState::Queued => State::Queued,
+        JobState::Running => State::Running,
+        JobState::Succeeded => State::Succeeded,
+        JobState::Failed => State::Failed,
+        JobState::Cancelled => State::Cancelled,
+    };
+
+    let mut reply = proto::Job {
+        id: job.id,
+        targets: job.targets.clone(),
+        error: job.error.clone().unwrap_or_default(),
+        created: timestamp(Some(job.created)),
+        started: timestamp(job.started),
+        finished: timestamp(job.finished),
+        builds: job.builds.iter().map(build_record).collect(),
+        ..Default::default()
+    };
+    reply.set_kind(kind);
+    reply.set_state(state);
+    reply
+}
@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tonic::transport::NamedService;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::Service;
+
+/// Serving status reported via the gRPC health checking service.
+///
+/// The overall server status and the arcanist service status are always updated together.
+#[derive(Debug, Clone)]
+pub struct Health {
+    reporter: HealthReporter,
+    stopping: Arc<Mutex<bool>>,
+}
+
+impl Health {
+    pub fn new(reporter: HealthReporter) -> Self {
+        Health {
+            reporter,
+            stopping: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    async fn set(&self, status: ServingStatus) {
+        let mut reporter = self.reporter.clone();
+        reporter.set_service_status("", status).await;
+        reporter.set_service_status(Service::NAME, status).await;
+    }
+
+    /// Report the daemon as serving requests, ignored once shutting down.
+    pub async fn serving(&self) {
+        let stopping = self.stopping.lock().await;
+        if !*stopping {
+            self.set(ServingStatus::Serving).await;
+        }
+    }
+
+    /// Report the daemon as temporarily not serving requests, e.g. while loading config.
+    pub async fn not_serving(&self) {
+        let _stopping = self.stopping.lock().await;
+        self.set(ServingStatus::NotServing).await;
+    }
+
+    /// Report the daemon as not serving requests for the rest of its lifetime.
+    pub async fn stopping(&self) {
+        let mut stopping = self.stopping.lock().await;
+        *stopping = true;
+        self.set(ServingStatus::NotServing).await;
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::sleep;
+use tonic::transport::server::Connected;
+
+use crate::jobs::JobManager;
+
+// interval between idle checks
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counter of open client connections.
+#[derive(Debug, Default, Clone)]
+pub struct Connections(Arc<AtomicUsize>);
+
+impl Connections {
+    /// Wrap a connection so it's counted while open.
+    pub fn track<T>(&self, stream: T) -> Tracked<T> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Tracked {
+            inner: stream,
+            count: self.0.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Connection that is counted until dropped.
+#[derive(Debug)]
+pub struct Tracked<T> {
+    inner: T,
+    count: Arc<AtomicUsize>,
+}
+
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T: Connected> Connected for Tracked<T> {
+    type ConnectInfo = T::ConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Resolve once there have been no open connections or unfinished jobs for a given duration.
+pub async fn wait(connections: Connections, jobs: Arc<JobManager>, timeout: Duration) {
+    let mut idle_since = Instant::now();
+    loop {
+        sleep(IDLE_POLL_INTERVAL).await;
+        if connections.count() > 0 || !jobs.is_idle() {
+            idle_since = Instant::now();
+        } else if idle_since.elapsed() >= timeout {
+            return;
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use pkgcraft::atom::{self, Atom};
+use pkgcraft::pkg::{ebuild::Pkg, Package};
+use pkgcraft::restrict::{self, Restrict, Restriction};
+use serde::{Deserialize, Serialize};
+
+use crate::build;
+
+/// Package installed to the system root by arcanist.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstalledPkg {
+    pub cpv: String,
+    pub repo: String,
+    pub slot: String,
+    pub deps: Vec<String>,
+    pub files: Vec<PathBuf>,
+}
+
+impl InstalledPkg {
+    pub(crate) fn new(pkg: &Pkg, files: Vec<PathBuf>) -> Self {
+        InstalledPkg {
+            cpv: pkg.atom().to_string(),
+            repo: pkg.repo().id().to_string(),
+            slot: pkg.slot().to_string(),
+            deps: build::pkg_deps(pkg).iter().map(|a| a.to_string()).collect(),
+            files,
+        }
+    }
+
+    pub fn atom(&self) -> Result<Atom> {
+        atom::cpv(&self.cpv).map_err(|e| anyhow!("invalid installed package: {e}"))
+    }
+}
+
+impl fmt::Display for InstalledPkg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.cpv, self.repo)
+    }
+}
+
+/// On-disk database of installed packages, stored as one file per package.
+#[derive(Debug)]
+pub struct Database {
+    path: PathBuf,
+}
+
+impl Database {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Database {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn pkg_path(&self, pkg: &InstalledPkg) -> PathBuf {
+        self.path.join(format!("{}.json", pkg.cpv))
+    }
+
+    /// Return all installed packages.
+    pub fn pkgs(&self) -> Result<Vec<InstalledPkg>> {
+        let mut pkgs = Vec::new();
+        let categories = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(pkgs),
+            Err(e) => return Err(e).context(format!("failed reading db: {:?}", self.path)),
+        };
+
+        for entry in categories {
+            for entry in fs::read_dir(entry?.path())? {
+                let path = entry?.path();
+                let data = fs::read(&path).context(format!("failed reading: {path:?}"))?;
+                let pkg = serde_json::from_slice(&data)
+                    .context(format!("invalid installed package: {path:?}"))?;
+                pkgs.push(pkg);
+            }
+        }
+
+        pkgs.sort_by(|a: &InstalledPkg, b| a.cpv.cmp(&b.cpv));
+        Ok(pkgs)
+    }
+
+    /// Return all installed packages matching the given restrictions.
+    pub fn matches(&self, restricts: &[Restrict]) -> Result<Vec<InstalledPkg>> {
+        let mut pkgs = Vec::new();
+        for pkg in self.pkgs()? {
+            let atom = pkg.atom()?;
+            if restricts.iter().any(|r| r.matches(&atom)) {
+                pkgs.push(pkg);
+            }
+        }
+        Ok(pkgs)
+    }
+
+    /// Return the remaining installed packages and their dependencies that would be left
+    /// unsatisfied if the given packages were removed.
+    pub fn rdeps(&self, pkgs: &[InstalledPkg]) -> Result<Vec<(InstalledPkg, String)>> {
+        let removed: HashSet<_> = pkgs.iter().map(|p| p.cpv.as_str()).collect();
+        let removed_atoms = pkgs.iter().map(|p| p.atom()).collect::<Result<Vec<_>>>()?;
+        let mut remaining = Vec::new();
+        for pkg in self.pkgs()? {
+            if !removed.contains(pkg.cpv.as_str()) {
+                let atom = pkg.atom()?;
+                remaining.push((pkg, atom));
+            }
+        }
+
+        let mut rdeps = Vec::new();
+        for (pkg, _) in remaining.iter() {
+            for dep in pkg.deps.iter() {
+                let restrict = restrict::parse::dep(dep).map_err(|e| anyhow!("{e}"))?;
+                let removing = removed_atoms.iter().any(|a| restrict.matches(a));
+                let satisfied = remaining.iter().any(|(_, a)| restrict.matches(a));
+                if removing && !satisfied {
+                    rdeps.push((pkg.clone(), dep.clone()));
+                }
+            }
+        }
+
+        Ok(rdeps)
+    }
+
+    /// Register a newly installed package, replacing any other installed version in the same
+    /// slot. Files of replaced versions that weren't reinstalled are removed from the given root.
+    pub fn add<P: AsRef<Path>>(&mut self, pkg: &InstalledPkg, root: P) -> Result<()> {
+        let atom = pkg.atom()?;
+        let mut replaced = Vec::new();
+        for p in self.pkgs()? {
+            let a = p.atom()?;
+            if p.cpv != pkg.cpv
+                && p.slot == pkg.slot
+                && a.category() == atom.category()
+                && a.package() == atom.package()
+            {
+                replaced.push(p);
+            }
+        }
+
+        let path = self.pkg_path(pkg);
+        let dir = path.parent().expect("invalid db path");
+        fs::create_dir_all(dir).context(format!("failed creating db dir: {dir:?}"))?;
+        let data = serde_json::to_vec(pkg)?;
+        fs::write(&path, data).context(format!("failed writing: {path:?}"))?;
+
+        // the new version is registered first so its files are left in place
+        for p in replaced {
+            self.unmerge(&p, root.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Remove a package's files from the given root and deregister it, returning the removed
+    /// files. Files owned by other installed packages are left in place.
+    pub fn unmerge<P: AsRef<Path>>(&mut self, pkg: &InstalledPkg, root: P) -> Result<Vec<PathBuf>> {
+        let root = root.as_ref();
+        let owned: HashSet<PathBuf> = self
+            .pkgs()?
+            .into_iter()
+            .filter(|p| p.cpv != pkg.cpv)
+            .flat_map(|p| p.files)
+            .collect();
+
+        let mut files = Vec::new();
+        for file in pkg.files.iter().filter(|f| !owned.contains(*f)) {
+            let path = root.join(file.strip_prefix("/").unwrap_or(file));
+            match fs::remove_file(&path) {
+                Ok(_) => files.push(file.clone()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e).context(format!("failed removing: {path:?}")),
+            }
+
+            // clean up emptied parent dirs, stopping at the first non-empty one
+            for dir in path.ancestors().skip(1).take_while(|p| *p != root) {
+                if fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+
+        let path = self.pkg_path(pkg);
+        fs::remove_file(&path).context(format!("failed removing: {path:?}"))?;
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    // Create an installed package record, writing its files to the given root.
+    fn pkg(root: &Path, cpv: &str, slot: &str, deps: &[&str], files: &[&str]) -> InstalledPkg {
+        for f in files {
+            let path = root.join(f.trim_start_matches('/'));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, cpv).unwrap();
+        }
+        InstalledPkg {
+            cpv: cpv.to_string(),
+            repo: "test".to_string(),
+            slot: slot.to_string(),
+            deps: deps.iter().map(|s| s.to_string()).collect(),
+            files: files.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    // Return the CPVs of all installed packages.
+    fn installed(db: &Database) -> Vec<String> {
+        db.pkgs().unwrap().into_iter().map(|p| p.cpv).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (dir, root) = (tempdir().unwrap(), tempdir().unwrap());
+        let root = root.path();
+        let mut db = Database::new(dir.path().join("db"));
+
+        // missing db dirs are treated as empty
+        assert!(db.pkgs().unwrap().is_empty());
+
+        let b = pkg(root, "cat/b-1", "0", &["cat/a"], &["/usr/bin/b"]);
+        let a = pkg(root, "cat/a-1", "0", &[], &["/usr/bin/a"]);
+        db.add(&b, root).unwrap();
+        db.add(&a, root).unwrap();
+        let pkgs = db.pkgs().unwrap();
+        assert_eq!(installed(&db), ["cat/a-1", "cat/b-1"]);
+        assert_eq!(pkgs[1].repo, "test");
+        assert_eq!(pkgs[1].slot, "0");
+        assert_eq!(pkgs[1].deps, ["cat/a"]);
+        assert_eq!(pkgs[1].files, [PathBuf::from("/usr/bin/b")]);
+        assert_eq!(pkgs[1].to_string(), "cat/b-1::test");
+
+        // matching
+        let restricts = [restrict::parse::dep("cat/b").unwrap()];
+        let matches = db.matches(&restricts).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].cpv, "cat/b-1");
+        let restricts = [restrict::parse::dep("cat/c").unwrap()];
+        assert!(db.matches(&restricts).unwrap().is_empty());
+
+        // unmerging removes files and emptied dirs while leaving shared files
+        let c = pkg(root, "cat/c-1", "0", &[], &["/usr/bin/b", "/opt/c/bin/c"]);
+        db.add(&c, root).unwrap();
+        let files = db.unmerge(&c, root).unwrap();
+        assert_eq!(files, [PathBuf::from("/opt/c/bin/c")]);
+        assert!(root.join("usr/bin/b").exists());
+        assert!(!root.join("opt").exists());
+        assert_eq!(installed(&db), ["cat/a-1", "cat/b-1"]);
+
+        // corrupt records are reported
+        fs::write(dir.path().join("db/cat/d-1.json"), "{").unwrap();
+        assert!(db.pkgs().is_err());
+    }
+
+    #[test]
+    fn test_rdeps() {
+        let (dir, root) = (tempdir().unwrap(), tempdir().unwrap());
+        let root = root.path();
+        let mut db = Database::new(dir.path());
+        let a1 = pkg(root, "cat/a-1", "1", &[], &[]);
+        let a2 = pkg(root, "cat/a-2", "2", &[], &[]);
+        let b = pkg(root, "cat/b-1", "0", &["cat/a", ">=cat/a-2"], &[]);
+        let c = pkg(root, "cat/c-1", "0", &["cat/b"], &[]);
+        for p in [&a1, &a2, &b, &c] {
+            db.add(p, root).unwrap();
+        }
+
+        // packages without dependents
+        assert!(db.rdeps(&[c.clone()]).unwrap().is_empty());
+        assert!(db.rdeps(&[b.clone(), c.clone()]).unwrap().is_empty());
+
+        // dependencies still satisfied by remaining packages
+        assert!(db.rdeps(&[a1.clone()]).unwrap().is_empty());
+
+        // dependencies left unsatisfied
+        let rdeps: Vec<_> = db
+            .rdeps(&[a2.clone()])
+            .unwrap()
+            .into_iter()
+            .map(|(p, dep)| (p.cpv, dep))
+            .collect();
+        assert_eq!(rdeps, [("cat/b-1".to_string(), ">=cat/a-2".to_string())]);
+        let rdeps: Vec<_> = db
+            .rdeps(&[a1, a2, b])
+            .unwrap()
+            .into_iter()
+            .map(|(p, dep)| (p.cpv, dep))
+            .collect();
+        assert_eq!(rdeps, [("cat/c-1".to_string(), "cat/b".to_string())]);
+    }
+
+    #[test]
+    fn test_add_replaces_slot() {
+        let (dir, root) = (tempdir().unwrap(), tempdir().unwrap());
+        let root = root.path();
+        let mut db = Database::new(dir.path());
+
+        let a1 = pkg(root, "cat/a-1", "0", &[], &["/usr/bin/a", "/usr/share/a/1"]);
+        db.add(&a1, root).unwrap();
+        assert_eq!(installed(&db), ["cat/a-1"]);
+
+        // upgrades replace the previous version, removing its files that weren't reinstalled
+        let a2 = pkg(root, "cat/a-2", "0", &[], &["/usr/bin/a", "/usr/share/a/2"]);
+        db.add(&a2, root).unwrap();
+        assert_eq!(installed(&db), ["cat/a-2"]);
+        assert_eq!(
+            fs::read_to_string(root.join("usr/bin/a")).unwrap(),
+            "cat/a-2"
+        );
+        assert!(!root.join("usr/share/a/1").exists());
+        assert!(root.join("usr/share/a/2").exists());
+
+        // reinstalls replace their own record
+        db.add(&a2, root).unwrap();
+        assert_eq!(installed(&db), ["cat/a-2"]);
+        assert!(root.join("usr/share/a/2").exists());
+
+        // versions in other slots and other packages are left alone
+        let a3 = pkg(root, "cat/a-3", "3", &[], &["/usr/share/a/3"]);
+        db.add(&a3, root).unwrap();
+        let b1 = pkg(root, "cat/b-1", "0", &[], &["/usr/bin/b"]);
+        db.add(&b1, root).unwrap();
+        assert_eq!(installed(&db), ["cat/a-2", "cat/a-3", "cat/b-1"]);
+    }
+}
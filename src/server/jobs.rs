@@ -0,0 +1,688 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, Semaphore};
+use tokio::time::timeout;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::metrics::Metrics;
+use crate::store::Store;
+
+// number of finished jobs kept in memory, older jobs are loaded from the store when requested
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JobKind {
+    Build,
+    Sync,
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// Outcome of building a single package within a job.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BuildRecord {
+    pub pkg: String,
+    pub error: Option<String>,
+    pub started: SystemTime,
+    pub duration: Duration,
+    pub files: Vec<PathBuf>,
+}
+
+impl BuildRecord {
+    /// Create a record for a finished build, using its result to determine the outcome.
+    pub fn new<S: ToString>(pkg: S, started: SystemTime, result: &Result<Vec<PathBuf>>) -> Self {
+        let (error, files) = match result {
+            Ok(files) => (None, files.clone()),
+            Err(e) => (Some(format!("{e:#}")), vec![]),
+        };
+        BuildRecord {
+            pkg: pkg.to_string(),
+            error,
+            started,
+            duration: started.elapsed().unwrap_or_default(),
+            files,
+        }
+    }
+}
+
+/// Snapshot of a job's status.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    pub state: JobState,
+    pub targets: Vec<String>,
+    pub error: Option<String>,
+    pub created: SystemTime,
+    pub started: Option<SystemTime>,
+    pub finished: Option<SystemTime>,
+    pub builds: Vec<BuildRecord>,
+    #[serde(skip)]
+    cancelled: Arc<AtomicBool>,
+    // wakes queued and running jobs when they're cancelled
+    #[serde(skip)]
+    wakeup: Arc<Notify>,
+}
+
+impl Job {
+    /// Create a newly queued job.
+    pub fn new(id: u64, kind: JobKind, targets: Vec<String>) -> Self {
+        Job {
+            id,
+            kind,
+            state: JobState::Queued,
+            targets,
+            error: None,
+            created: SystemTime::now(),
+            started: None,
+            finished: None,
+            builds: vec![],
+            cancelled: Arc::new(AtomicBool::new(false)),
+            wakeup: Arc::new(Notify::new()),
+        }
+    }
+}
+
+/// Handle passed to running jobs in order to support cancellation and logging.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    wakeup: Arc<Notify>,
+    manager: Arc<JobManager>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the job to be cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // register for notifications before checking to avoid missing wakeups
+            let wakeup = self.wakeup.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            wakeup.await;
+        }
+    }
+
+    /// Return an error if the job has been cancelled, used at points where jobs can be halted.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("job cancelled");
+        }
+        Ok(())
+    }
+
+    /// Open the job's log file for appending.
+    pub fn log_file(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.manager.log_path(self.id))
+    }
+
+    /// Append a line to the job's log.
+    pub fn log<S: AsRef<str>>(&self, msg: S) {
+        // logging failures shouldn't abort jobs
+        if let Ok(mut f) = self.log_file() {
+            writeln!(f, "{}", msg.as_ref()).unwrap_or_default();
+        }
+    }
+
+    /// Add a package build outcome to the job's history.
+    pub fn record_build(&self, record: BuildRecord) {
+        let metrics = &self.manager.metrics;
+        metrics.observe_build(record.error.is_none(), record.duration);
+        self.manager.update(self.id, |job| job.builds.push(record));
+    }
+
+    /// Record the outcome of syncing a repo.
+    pub fn record_sync(&self, repo: &str, started: Instant, success: bool) {
+        let metrics = &self.manager.metrics;
+        metrics.observe_sync(repo, success, started.elapsed());
+    }
+}
+
+/// Error returned when submitting jobs after the manager is closed.
+#[derive(Debug, thiserror::Error)]
+#[error("daemon shutting down")]
+pub struct Closed;
+
+/// Manager running background jobs in submission order with a limit on concurrency.
+#[derive(Debug)]
+pub struct JobManager {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    slots: Arc<Semaphore>,
+    log_dir: PathBuf,
+    store: Store,
+    metrics: Metrics,
+    closed: AtomicBool,
+    finished: Notify,
+}
+
+impl JobManager {
+    /// Create a job manager, continuing on from the job history in the given store.
+    pub fn new<P: AsRef<Path>>(
+        max_jobs: usize,
+        log_dir: P,
+        store: Store,
+        metrics: Metrics,
+    ) -> Result<Self> {
+        // IDs continue on from all stored records, including unreadable ones
+        let last_id = store.last_id()?;
+        for mut job in store.jobs()? {
+            // jobs left unfinished by a previous daemon instance can't be resumed
+            if !job.state.is_finished() {
+                job.state = JobState::Failed;
+                job.error = Some("interrupted by daemon restart".to_string());
+                job.finished = Some(SystemTime::now());
+                store.save(&job)?;
+            }
+        }
+
+        Ok(JobManager {
+            next_id: AtomicU64::new(last_id + 1),
+            jobs: Mutex::new(BTreeMap::new()),
+            slots: Arc::new(Semaphore::new(max_jobs)),
+            log_dir: log_dir.as_ref().to_path_buf(),
+            store,
+            metrics,
+            closed: AtomicBool::new(false),
+            finished: Notify::new(),
+        })
+    }
+
+    /// Return the path to a job's combined output log.
+    pub fn log_path(&self, id: u64) -> PathBuf {
+        self.log_dir.join(format!("{id}.log"))
+    }
+
+    // Persist a job's status, logging failures since they shouldn't abort jobs.
+    fn save(&self, job: &Job) {
+        if let Err(e) = self.store.save(job) {
+            warn!("failed saving job {}: {e:#}", job.id);
+        }
+    }
+
+    // Update a job's status, saving it while locked so concurrent updates are persisted in order.
+    fn update<F: FnOnce(&mut Job)>(&self, id: u64, func: F) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&id) {
+            func(job);
+            self.save(job);
+            if job.state.is_finished() {
+                Self::prune(&mut jobs);
+            }
+        }
+    }
+
+    // Drop the oldest finished jobs from memory beyond the retention limit.
+    fn prune(jobs: &mut BTreeMap<u64, Job>) {
+        let finished: Vec<_> = jobs
+            .values()
+            .filter(|job| job.state.is_finished())
+            .map(|job| job.id)
+            .collect();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_JOBS);
+        for id in &finished[..excess] {
+            jobs.remove(id);
+        }
+    }
+
+    /// Enqueue a job, returning its initial status.
+    ///
+    /// The given closure is called immediately with the job's handle while its returned future
+    /// is run once the job starts. Jobs cancelled while queued are still run without waiting for
+    /// a free slot so they can report their cancellation, halting at their first checkpoint.
+    pub fn spawn<F, Fut>(
+        self: &Arc<Self>,
+        kind: JobKind,
+        targets: Vec<String>,
+        func: F,
+    ) -> Result<Job>
+    where
+        F: FnOnce(JobHandle) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        // jobs can't be submitted during shutdown
+        if self.closed.load(Ordering::SeqCst) {
+            bail!(Closed);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let job = Job::new(id, kind, targets);
+        let handle = JobHandle {
+            id,
+            cancelled: job.cancelled.clone(),
+            wakeup: job.wakeup.clone(),
+            manager: self.clone(),
+        };
+        let wakeup = job.wakeup.clone();
+        // saved before being added so it can't be updated concurrently
+        self.save(&job);
+        self.jobs.lock().unwrap().insert(id, job.clone());
+
+        // start each job with an empty log, removing any stale one
+        if let Err(e) = fs::create_dir_all(&self.log_dir) {
+            warn!("failed creating log dir: {:?}: {e}", self.log_dir);
+        }
+        fs::remove_file(self.log_path(id)).unwrap_or_default();
+
+        // jobs are traced as part of the request submitting them
+        let manager = self.clone();
+        let span = info_span!("job", id);
+        let future = func(handle.clone());
+        let job_task = async move {
+            let _permit = tokio::select! {
+                permit = manager.slots.clone().acquire_owned() => Some(permit),
+                _ = wakeup.notified() => None,
+            };
+
+            // jobs cancelled while queued were already marked as finished
+            manager.update(id, |job| {
+                if job.state == JobState::Queued {
+                    job.state = JobState::Running;
+                    job.started = Some(SystemTime::now());
+                }
+            });
+
+            let result = future.await;
+            if let Err(e) = &result {
+                handle.log(format!("error: {e:#}"));
+            }
+
+            manager.update(id, |job| {
+                job.finished.get_or_insert_with(SystemTime::now);
+                job.state = match result {
+                    _ if handle.is_cancelled() => JobState::Cancelled,
+                    Ok(_) => JobState::Succeeded,
+                    Err(e) => {
+                        job.error = Some(format!("{e:#}"));
+                        JobState::Failed
+                    }
+                };
+            });
+            manager.finished.notify_waiters();
+        };
+        tokio::spawn(job_task.instrument(span));
+
+        Ok(job)
+    }
+
+    /// Return all unfinished and recently finished jobs run by the current daemon instance
+    /// ordered by ID.
+    pub fn list(&self) -> Vec<Job> {
+        self.jobs.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Return a job, falling back to the job history for previous daemon instances.
+    pub fn get(&self, id: u64) -> Option<Job> {
+        if let Some(job) = self.jobs.lock().unwrap().get(&id) {
+            return Some(job.clone());
+        }
+        self.store.get(id).unwrap_or_else(|e| {
+            warn!("failed loading job {id}: {e:#}");
+            None
+        })
+    }
+
+    /// Return the job history, most recent first, optionally limited to a number of entries.
+    pub fn history(&self, limit: Option<usize>) -> Result<Vec<Job>> {
+        let mut jobs = self.store.jobs()?;
+        jobs.reverse();
+        if let Some(limit) = limit {
+            jobs.truncate(limit);
+        }
+        Ok(jobs)
+    }
+
+    /// Request a job to be cancelled, returning its updated status.
+    ///
+    /// Queued jobs are cancelled immediately while running jobs halt at their next checkpoint,
+    /// killing any child processes they're waiting on.
+    pub fn cancel(&self, id: u64) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match jobs.get_mut(&id) {
+            Some(job) => job,
+            // pruned jobs have already finished
+            None => {
+                drop(jobs);
+                return self.get(id);
+            }
+        };
+        if !job.state.is_finished() {
+            job.cancelled.store(true, Ordering::SeqCst);
+            if job.state == JobState::Queued {
+                job.state = JobState::Cancelled;
+                job.finished = Some(SystemTime::now());
+                job.wakeup.notify_one();
+            } else {
+                job.wakeup.notify_waiters();
+            }
+        }
+        self.save(job);
+        let job = job.clone();
+        drop(jobs);
+        if job.state.is_finished() {
+            self.finished.notify_waiters();
+        }
+        Some(job)
+    }
+
+    // Return the IDs of all unfinished jobs.
+    fn active(&self) -> Vec<u64> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| !job.state.is_finished())
+            .map(|job| job.id)
+            .collect()
+    }
+
+    /// Return the number of jobs in a given state.
+    pub fn count(&self, state: JobState) -> usize {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| job.state == state)
+            .count()
+    }
+
+    /// Determine if there are no unfinished jobs.
+    pub fn is_idle(&self) -> bool {
+        self.active().is_empty()
+    }
+
+    /// Wait for all unfinished jobs to complete.
+    pub async fn wait(&self) {
+        loop {
+            // register for notifications before checking to avoid missing wakeups
+            let finished = self.finished.notified();
+            if self.is_idle() {
+                return;
+            }
+            finished.await;
+        }
+    }
+
+    /// Stop accepting new jobs.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop accepting jobs and drain the queue.
+    ///
+    /// Queued jobs are cancelled while running jobs are given the grace period to finish before
+    /// being cancelled. Jobs that still haven't halted after a further grace period are recorded
+    /// as failed, returning false so the caller can force them to stop.
+    pub async fn shutdown(&self, grace: Duration) -> bool {
+        self.close();
+
+        let queued: Vec<_> = self
+            .list()
+            .into_iter()
+            .filter(|job| job.state == JobState::Queued)
+            .collect();
+        for job in queued {
+            self.cancel(job.id);
+        }
+
+        if timeout(grace, self.wait()).await.is_ok() {
+            return true;
+        }
+
+        let active = self.active();
+        info!("cancelling {} running job(s)", active.len());
+        for id in active {
+            self.cancel(id);
+        }
+
+        if timeout(grace, self.wait()).await.is_ok() {
+            return true;
+        }
+
+        for id in self.active() {
+            warn!("job {id} failed to halt");
+            self.update(id, |job| {
+                job.state = JobState::Failed;
+                job.error = Some("interrupted by daemon shutdown".to_string());
+                job.finished = Some(SystemTime::now());
+            });
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use tempfile::tempdir;
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    fn manager(dir: &Path, max_jobs: usize) -> Arc<JobManager> {
+        let store = Store::new(dir.join("jobs"));
+        let metrics = Metrics::new().unwrap();
+        Arc::new(JobManager::new(max_jobs, dir.join("logs"), store, metrics).unwrap())
+    }
+
+    // Spawn a job that runs until released, returning its status and release trigger.
+    async fn blocking_job(jobs: &Arc<JobManager>) -> (Job, oneshot::Sender<()>) {
+        let (release_tx, release_rx) = oneshot::channel();
+        let (started_tx, started_rx) = oneshot::channel();
+        let job = jobs
+            .spawn(JobKind::Build, vec![], move |_| async move {
+                started_tx.send(()).unwrap();
+                release_rx.await?;
+                Ok(())
+            })
+            .unwrap();
+        started_rx.await.unwrap();
+        (job, release_tx)
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let dir = tempdir().unwrap();
+        let jobs = manager(dir.path(), 1);
+        let (job1, release) = blocking_job(&jobs).await;
+
+        // jobs beyond the concurrency limit are queued
+        let job2 = jobs
+            .spawn(JobKind::Sync, vec!["repo".to_string()], |_| async {
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(job2.state, JobState::Queued);
+        assert_eq!(jobs.get(job1.id).unwrap().state, JobState::Running);
+        assert_eq!(jobs.count(JobState::Running), 1);
+        assert_eq!(jobs.count(JobState::Queued), 1);
+        assert!(!jobs.is_idle());
+
+        // queued jobs start once running ones finish
+        release.send(()).unwrap();
+        jobs.wait().await;
+        for id in [job1.id, job2.id] {
+            let job = jobs.get(id).unwrap();
+            assert_eq!(job.state, JobState::Succeeded);
+            assert!(job.started.is_some() && job.finished.is_some());
+        }
+
+        // finished jobs are persisted
+        let history: Vec<_> = jobs.history(None).unwrap().iter().map(|j| j.id).collect();
+        assert_eq!(history, [job2.id, job1.id]);
+
+        // failed jobs record their error
+        let job = jobs
+            .spawn(JobKind::Remove, vec![], |_| async { bail!("failed") })
+            .unwrap();
+        jobs.wait().await;
+        let job = jobs.get(job.id).unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.unwrap(), "failed");
+
+        // closed managers reject new jobs
+        jobs.close();
+        let err = jobs
+            .spawn(JobKind::Build, vec![], |_| async { Ok(()) })
+            .unwrap_err();
+        assert!(err.is::<Closed>());
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let dir = tempdir().unwrap();
+        let jobs = manager(dir.path(), 1);
+        let (job1, release) = blocking_job(&jobs).await;
+
+        // jobs cancelled while queued still run to report it without waiting for a slot
+        let (tx, rx) = oneshot::channel();
+        let job2 = jobs
+            .spawn(JobKind::Build, vec![], move |job| async move {
+                let result = job.check();
+                tx.send(result.is_err()).unwrap();
+                result
+            })
+            .unwrap();
+        let job = jobs.cancel(job2.id).unwrap();
+        assert_eq!(job.state, JobState::Cancelled);
+        assert!(job.finished.is_some());
+        assert!(rx.await.unwrap());
+
+        // running jobs are cancelled at their next checkpoint
+        let job = jobs.cancel(job1.id).unwrap();
+        assert_eq!(job.state, JobState::Running);
+        release.send(()).unwrap();
+        jobs.wait().await;
+        assert_eq!(jobs.get(job1.id).unwrap().state, JobState::Cancelled);
+        assert_eq!(jobs.get(job2.id).unwrap().state, JobState::Cancelled);
+        assert!(jobs.get(job2.id).unwrap().started.is_none());
+
+        // cancelling finished jobs leaves them unchanged
+        let job = jobs.cancel(job1.id).unwrap();
+        assert_eq!(job.state, JobState::Cancelled);
+
+        // unknown jobs
+        assert!(jobs.cancel(9999).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let dir = tempdir().unwrap();
+        let jobs = manager(dir.path(), 1);
+        let (job1, release) = blocking_job(&jobs).await;
+
+        // running jobs halting once cancelled finish cleanly
+        let (result, _) = tokio::join!(jobs.shutdown(Duration::from_secs(5)), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            release.send(()).unwrap();
+        });
+        assert!(result);
+        assert_eq!(jobs.get(job1.id).unwrap().state, JobState::Succeeded);
+        assert!(jobs
+            .spawn(JobKind::Build, vec![], |_| async { Ok(()) })
+            .is_err());
+
+        // stuck jobs don't block shutdown and are marked as failed
+        let jobs = manager(dir.path(), 1);
+        let (started_tx, started_rx) = oneshot::channel();
+        let job = jobs
+            .spawn(JobKind::Build, vec![], move |_| async move {
+                started_tx.send(()).unwrap();
+                future::pending().await
+            })
+            .unwrap();
+        started_rx.await.unwrap();
+        let result = timeout(Duration::from_secs(5), jobs.shutdown(Duration::ZERO)).await;
+        assert!(!result.unwrap());
+        let job = jobs.get(job.id).unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.unwrap(), "interrupted by daemon shutdown");
+    }
+
+    #[tokio::test]
+    async fn test_interrupted() {
+        let dir = tempdir().unwrap();
+        let store = Store::new(dir.path().join("jobs"));
+        let mut job = Job::new(1, JobKind::Build, vec![]);
+        job.state = JobState::Succeeded;
+        store.save(&job).unwrap();
+        store.save(&Job::new(2, JobKind::Build, vec![])).unwrap();
+        let mut job = Job::new(3, JobKind::Sync, vec![]);
+        job.state = JobState::Running;
+        store.save(&job).unwrap();
+        fs::write(dir.path().join("jobs/4.json"), "{").unwrap();
+
+        // jobs left unfinished by previous instances are marked as failed
+        let jobs = manager(dir.path(), 1);
+        let job = jobs.get(1).unwrap();
+        assert_eq!(job.state, JobState::Succeeded);
+        assert!(job.error.is_none());
+        for id in [2, 3] {
+            let job = jobs.get(id).unwrap();
+            assert_eq!(job.state, JobState::Failed);
+            assert_eq!(job.error.unwrap(), "interrupted by daemon restart");
+            assert!(job.finished.is_some());
+        }
+
+        // new jobs continue on from the previous IDs, including those of corrupt records
+        let job = jobs
+            .spawn(JobKind::Build, vec![], |_| async { Ok(()) })
+            .unwrap();
+        assert_eq!(job.id, 5);
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let dir = tempdir().unwrap();
+        let jobs = manager(dir.path(), 1);
+        let total = MAX_FINISHED_JOBS + 5;
+        for _ in 0..total {
+            jobs.spawn(JobKind::Sync, vec![], |_| async { Ok(()) })
+                .unwrap();
+            jobs.wait().await;
+        }
+
+        // old jobs are dropped from memory but still available from the store
+        let ids: Vec<_> = jobs.list().iter().map(|j| j.id).collect();
+        assert_eq!(ids.len(), MAX_FINISHED_JOBS);
+        assert_eq!(ids[0], 6);
+        assert_eq!(jobs.get(1).unwrap().state, JobState::Succeeded);
+        assert_eq!(jobs.cancel(1).unwrap().state, JobState::Succeeded);
+    }
+}
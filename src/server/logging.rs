@@ -0,0 +1,89 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use file_rotate::{compression::Compression, suffix::AppendCount};
+use file_rotate::{ContentLimit, FileRotate, TimeFrequency};
+use nix::unistd::isatty;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::{self, writer::BoxMakeWriter};
+use tracing_subscriber::{prelude::*, registry, reload, Registry};
+
+use crate::settings::{LogFormat, LogRotation, Settings};
+
+/// Handle used to change the log filter of a running daemon.
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+// Convert a verbosity level to a tracing level, defaulting to warning level.
+fn level(verbosity: i32) -> LevelFilter {
+    match verbosity {
+        i32::MIN..=-2 => LevelFilter::OFF,
+        -1 => LevelFilter::ERROR,
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        3..=i32::MAX => LevelFilter::TRACE,
+    }
+}
+
+/// Create the log filter for the given settings.
+///
+/// The verbosity determines the default level, overridden per module by the configured filter
+/// directives and then by those in the RUST_LOG environment variable.
+pub fn filter(settings: &Settings) -> Result<EnvFilter> {
+    let mut filter = EnvFilter::default().add_directive(level(settings.verbosity).into());
+    let env_directives = env::var("RUST_LOG").unwrap_or_default();
+    for directives in [settings.log_filter.as_str(), env_directives.as_str()] {
+        for directive in directives.split(',').filter(|s| !s.trim().is_empty()) {
+            let directive = directive
+                .trim()
+                .parse()
+                .context(format!("invalid log filter directive: {directive}"))?;
+            filter = filter.add_directive(directive);
+        }
+    }
+    Ok(filter)
+}
+
+// Create the writer for log output, optionally rotating log files by size or time.
+fn writer(settings: &Settings) -> Result<BoxMakeWriter> {
+    if settings.log_file.is_empty() {
+        return Ok(BoxMakeWriter::new(io::stderr));
+    }
+
+    let limit = match (settings.log_max_size, settings.log_rotate) {
+        (0, LogRotation::Never) => ContentLimit::None,
+        (0, LogRotation::Hourly) => ContentLimit::Time(TimeFrequency::Hourly),
+        (0, LogRotation::Daily) => ContentLimit::Time(TimeFrequency::Daily),
+        (0, LogRotation::Weekly) => ContentLimit::Time(TimeFrequency::Weekly),
+        (size, LogRotation::Never) => ContentLimit::BytesSurpassed(size),
+        _ => bail!("log files can't be rotated by both size and time"),
+    };
+
+    let path = Path::new(&settings.log_file);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("failed creating log dir: {dir:?}"))?;
+    }
+    let suffix = AppendCount::new(settings.log_keep);
+    let file = FileRotate::new(path, suffix, limit, Compression::None, None);
+    Ok(BoxMakeWriter::new(Mutex::new(file)))
+}
+
+/// Install the global tracing subscriber, returning a handle used to reload its filter.
+pub fn init(settings: &Settings) -> Result<FilterHandle> {
+    let (filter, handle) = reload::Layer::new(filter(settings)?);
+    // colored output is only used when logging to a terminal
+    let ansi = settings.log_file.is_empty() && isatty(io::stderr().as_raw_fd()).unwrap_or_default();
+    let layer = fmt::layer().with_writer(writer(settings)?).with_ansi(ansi);
+    let layer = match settings.log_format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    };
+    let subscriber = registry().with(filter).with(layer);
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    Ok(handle)
+}
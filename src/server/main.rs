@@ -1,22 +1,68 @@
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::Write;
 use std::net::SocketAddr;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::process;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use clap::{Arg, Command};
-use futures::TryFutureExt;
+use arcanist::ReadyInfo;
+use clap::{Arg, ArgMatches, Command};
+use futures::future::{self, BoxFuture, FutureExt, Shared, TryFutureExt};
+use futures::TryStreamExt;
 use pkgcraft::config::Config as PkgcraftConfig;
 use tokio::net::{TcpListener, UnixListener};
-use tokio::sync::RwLock;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{Notify, RwLock};
 use tokio_stream::wrappers::TcpListenerStream;
-use tonic::transport::Server;
-use tracing_subscriber::{filter::LevelFilter, fmt};
+use tonic::transport::server::Router;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tower::layer::util::Stack;
+use tracing::{error, info};
 
+use crate::auth::{AuthLayer, Policy};
+use crate::health::Health;
+use crate::idle::Connections;
+use crate::installed::Database;
+use crate::jobs::JobManager;
+use crate::metrics::{Metrics, MetricsLayer};
+use crate::reload::Reloader;
+use crate::repos::Repos;
 use crate::service::ArcanistService;
-use crate::settings::Settings;
+use crate::settings::{Access, Listener, Settings};
+use crate::store::Store;
+use crate::systemd::NotifyState;
+use crate::trace::TraceLayer;
+use crate::uds::SocketPerms;
+use crate::worker::Worker;
 
+mod auth;
+mod build;
+mod convert;
+mod health;
+mod idle;
+mod installed;
+mod jobs;
+mod logging;
+mod metrics;
+mod reload;
+mod repos;
 mod service;
 mod settings;
+mod store;
+mod systemd;
+mod trace;
 mod uds;
+mod worker;
+
+type Service = arcanist::Server<ArcanistService>;
+type Shutdown = Shared<BoxFuture<'static, ()>>;
+type Layers =
+    Stack<AuthLayer, Stack<MetricsLayer, Stack<TraceLayer, tower::layer::util::Identity>>>;
+// Layers a server and registers all services on it, shared between listeners.
+type Routes = Arc<dyn Fn(Server, AuthLayer) -> Router<Layers> + Send + Sync>;
 
 #[rustfmt::skip]
 pub fn cmd() -> Command<'static> {
@@ -39,9 +85,54 @@ pub fn cmd() -> Command<'static> {
         .arg(Arg::new("socket")
             .takes_value(true)
             .forbid_empty_values(true)
+            .multiple_occurrences(true)
             .long("bind")
             .value_name("IP:port")
-            .help("bind to given network socket"))
+            .help("bind to given network socket, can be repeated"))
+        .arg(Arg::new("shutdown-timeout")
+            .takes_value(true)
+            .long("shutdown-timeout")
+            .value_name("SECONDS")
+            .validator(|s| s.parse::<u64>())
+            .help("grace period for running jobs on shutdown"))
+        .arg(Arg::new("idle-timeout")
+            .takes_value(true)
+            .long("idle-timeout")
+            .value_name("SECONDS")
+            .validator(|s| s.parse::<u64>())
+            .help("exit after being idle for a given duration"))
+        .arg(Arg::new("tls-cert")
+            .takes_value(true)
+            .forbid_empty_values(true)
+            .long("tls-cert")
+            .value_name("PATH")
+            .requires("tls-key")
+            .help("serve TLS on network sockets using the given certificate"))
+        .arg(Arg::new("tls-key")
+            .takes_value(true)
+            .forbid_empty_values(true)
+            .long("tls-key")
+            .value_name("PATH")
+            .requires("tls-cert")
+            .help("private key for the TLS certificate"))
+        .arg(Arg::new("tls-client-ca")
+            .takes_value(true)
+            .forbid_empty_values(true)
+            .long("tls-client-ca")
+            .value_name("PATH")
+            .help("require client certificates signed by the given CA"))
+        .arg(Arg::new("metrics")
+            .takes_value(true)
+            .long("metrics")
+            .value_name("IP:port")
+            .validator(|s| s.parse::<SocketAddr>())
+            .help("serve Prometheus metrics over HTTP on the given socket"))
+        .arg(Arg::new("ready-fd")
+            .takes_value(true)
+            .long("ready-fd")
+            .value_name("FD")
+            .validator(|s| s.parse::<RawFd>())
+            .help("write startup info as JSON to the given file descriptor"))
         .arg(Arg::new("config")
             .takes_value(true)
             .forbid_empty_values(true)
@@ -51,11 +142,23 @@ pub fn cmd() -> Command<'static> {
         .arg(Arg::new("config-none")
             .long("config-none")
             .help("don't load config file"))
+        .arg(Arg::new("build")
+            .takes_value(true)
+            .long("build")
+            .value_name("PKG")
+            .hide(true)
+            .help("build a package, used internally to run builds in child processes"))
+        .arg(Arg::new("sync")
+            .takes_value(true)
+            .long("sync")
+            .value_name("REPO")
+            .hide(true)
+            .conflicts_with("build")
+            .help("sync a repo, used internally to run syncs in child processes"))
 }
 
-fn load_settings() -> Result<(Settings, PkgcraftConfig)> {
-    let app = cmd();
-    let args = app.get_matches();
+// Load settings and pkgcraft config, overriding them with command-line settings.
+fn load_settings(args: &ArgMatches) -> Result<(Settings, PkgcraftConfig)> {
     let config_file = args.value_of("config");
     let skip_config = args.is_present("config-none");
 
@@ -72,57 +175,232 @@ fn load_settings() -> Result<(Settings, PkgcraftConfig)> {
     settings.verbosity += args.occurrences_of("verbose") as i32;
     settings.verbosity -= args.occurrences_of("quiet") as i32;
 
-    if let Some(socket) = args.value_of("socket") {
-        settings.socket = socket.to_string();
+    if let Some(sockets) = args.values_of("socket") {
+        settings.socket = sockets.map(Listener::new).collect();
     } else if settings.socket.is_empty() {
         // default to using unix domain socket
-        settings.socket = config.path.run.join("arcanist.sock").to_string();
+        let socket = config.path.run.join("arcanist.sock");
+        settings.socket = vec![Listener::new(socket)];
     }
 
-    // defaults to warning level
-    let tracing_filter = match settings.verbosity {
-        i32::MIN..=-2 => LevelFilter::OFF,
-        -1 => LevelFilter::ERROR,
-        0 => LevelFilter::WARN,
-        1 => LevelFilter::INFO,
-        2 => LevelFilter::DEBUG,
-        3..=i32::MAX => LevelFilter::TRACE,
-    };
+    // default to installing packages to the system root
+    if settings.root.is_empty() {
+        settings.root = "/".to_string();
+    }
 
-    let subscriber = fmt().with_max_level(tracing_filter).finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    // default to running jobs serially
+    if settings.jobs == 0 {
+        settings.jobs = 1;
+    }
+
+    if let Some(secs) = args.value_of("shutdown-timeout") {
+        settings.shutdown_timeout = secs.parse().unwrap();
+    }
+
+    if let Some(secs) = args.value_of("idle-timeout") {
+        settings.idle_timeout = secs.parse().unwrap();
+    }
+
+    if let Some(path) = args.value_of("tls-cert") {
+        settings.tls_cert = path.to_string();
+    }
+    if let Some(path) = args.value_of("tls-key") {
+        settings.tls_key = path.to_string();
+    }
+    if let Some(path) = args.value_of("tls-client-ca") {
+        settings.tls_client_ca = path.to_string();
+    }
+    if let Some(socket) = args.value_of("metrics") {
+        settings.metrics = socket.to_string();
+    }
 
     Ok((settings, config))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let (settings, config) = load_settings()?;
-    let socket = settings.socket.clone();
-    let service = ArcanistService {
-        settings,
-        config: Arc::new(RwLock::new(config)),
+// Load the TLS config for network sockets, if enabled.
+fn tls_config(settings: &Settings) -> Result<Option<ServerTlsConfig>> {
+    let (cert, key, client_ca) = (
+        &settings.tls_cert,
+        &settings.tls_key,
+        &settings.tls_client_ca,
+    );
+    if cert.is_empty() && key.is_empty() {
+        if !client_ca.is_empty() {
+            bail!("TLS client CA set without a certificate and key");
+        }
+        return Ok(None);
+    } else if cert.is_empty() || key.is_empty() {
+        bail!("TLS requires both a certificate and key");
+    }
+
+    let read = |path: &str| fs::read(path).context(format!("failed reading: {path}"));
+    let identity = Identity::from_pem(read(cert)?, read(key)?);
+    let mut config = ServerTlsConfig::new().identity(identity);
+    if !client_ca.is_empty() {
+        config = config.client_ca_root(Certificate::from_pem(read(client_ca)?));
+    }
+    Ok(Some(config))
+}
+
+// Resolve once a termination signal, shutdown request, or idle timeout is received.
+async fn shutdown_signal<F: Future<Output = ()>>(request: Arc<Notify>, idle: F) {
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed registering SIGTERM handler");
+    tokio::select! {
+        _ = sigterm.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+        _ = request.notified() => (),
+        _ = idle => (),
+    }
+    info!("shutting down");
+}
+
+// Report startup info to the process that spawned the daemon, closing the descriptor afterwards.
+fn report_ready(fd: RawFd, sockets: Vec<String>, metrics: Option<String>) -> Result<()> {
+    let info = ReadyInfo {
+        sockets,
+        pid: process::id(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_version: arcanist::API_VERSION,
+        metrics,
+    };
+    let mut f = unsafe { File::from_raw_fd(fd) };
+    let mut data = serde_json::to_vec(&info)?;
+    data.push(b'\n');
+    f.write_all(&data).context("failed writing startup info")
+}
+
+// Create the authorization layer for a listener.
+//
+// Trusted access is only allowed on unix domain sockets, guarded by their file permissions, or
+// network sockets requiring TLS client certificates.
+fn auth_layer(listener: &Listener, settings: &Settings, uds: bool) -> Result<AuthLayer> {
+    if listener.access == Access::Trusted && !uds && settings.tls_client_ca.is_empty() {
+        bail!(
+            "trusted access requires TLS client authentication on network sockets: {}",
+            listener.socket
+        );
+    }
+
+    let admin_group = match listener.admin_group.is_empty() {
+        true => &settings.admin_group,
+        false => &listener.admin_group,
     };
-    let server = Server::builder().add_service(arcanist::Server::new(service));
+    Ok(AuthLayer::new(Policy::new(listener.access, admin_group)?))
+}
+
+// Serve requests on a unix domain socket until shutdown, removing the given socket path after.
+fn serve_uds(
+    listener: UnixListener,
+    path: Option<String>,
+    auth: AuthLayer,
+    routes: Routes,
+    connections: Connections,
+    shutdown: Shutdown,
+) -> BoxFuture<'static, Result<()>> {
+    let incoming = {
+        async_stream::stream! {
+            loop {
+                let item = listener
+                    .accept()
+                    .map_ok(|(st, _)| connections.track(uds::UnixStream(st)))
+                    .await;
+                yield item;
+            }
+        }
+    };
+    async move {
+        let result = routes(Server::builder(), auth)
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .await;
+        if let Some(path) = path {
+            fs::remove_file(path).unwrap_or_default();
+        }
+        Ok(result?)
+    }
+    .boxed()
+}
+
+// Serve requests on a network socket until shutdown.
+fn serve_tcp(
+    listener: TcpListener,
+    settings: &Settings,
+    auth: AuthLayer,
+    routes: Routes,
+    connections: Connections,
+    shutdown: Shutdown,
+) -> Result<BoxFuture<'static, Result<()>>> {
+    let incoming = TcpListenerStream::new(listener).map_ok(move |st| connections.track(st));
+    let mut server = Server::builder();
+    if let Some(tls) = tls_config(settings)? {
+        server = server.tls_config(tls).context("invalid TLS config")?;
+    }
+    Ok(async move {
+        routes(server, auth)
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .await?;
+        Ok(())
+    }
+    .boxed())
+}
+
+// Unix domain socket files bound on startup, removed when dropped unless released.
+#[derive(Debug, Default)]
+struct SocketFiles(Vec<String>);
+
+impl SocketFiles {
+    // Stop tracking the socket files, leaving them in place.
+    fn release(mut self) {
+        self.0.clear();
+    }
+}
+
+impl Drop for SocketFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            fs::remove_file(path).unwrap_or_default();
+        }
+    }
+}
+
+// Bind to a socket, returning a future that serves requests on it until shutdown.
+async fn bind(
+    listener: &Listener,
+    settings: &Settings,
+    routes: Routes,
+    connections: Connections,
+    shutdown: Shutdown,
+) -> Result<(String, BoxFuture<'static, Result<()>>)> {
+    let socket = listener.socket.clone();
 
     match socket.parse::<SocketAddr>() {
         // force unix domain sockets to be absolute paths
         Err(_) if socket.starts_with('/') => {
+            let auth = auth_layer(listener, settings, true)?;
+            let perms = SocketPerms::new(
+                &settings.socket_mode,
+                &settings.socket_owner,
+                &settings.socket_group,
+            )?;
             uds::verify_socket_path(&socket)?;
             let listener = UnixListener::bind(&socket)
                 .context(format!("failed binding to socket: {socket}"))?;
+            if let Err(e) = perms.apply(&socket) {
+                fs::remove_file(&socket).unwrap_or_default();
+                return Err(e);
+            }
             eprintln!("arcanist listening at: {socket}");
-            let incoming = {
-                async_stream::stream! {
-                    loop {
-                        let item = listener.accept().map_ok(|(st, _)| uds::UnixStream(st)).await;
-                        yield item;
-                    }
-                }
-            };
-            server.serve_with_incoming(incoming).await?;
+            let server = serve_uds(
+                listener,
+                Some(socket.clone()),
+                auth,
+                routes,
+                connections,
+                shutdown,
+            );
+            Ok((socket, server))
         }
         Ok(socket) => {
+            let auth = auth_layer(listener, settings, false)?;
             let listener = TcpListener::bind(&socket)
                 .await
                 .context(format!("failed binding to socket: {socket}"))?;
@@ -130,11 +408,297 @@ async fn main() -> Result<()> {
                 .local_addr()
                 .context(format!("invalid local address: {socket}"))?;
             eprintln!("arcanist listening at: {addr}");
-            let incoming = TcpListenerStream::new(listener);
-            server.serve_with_incoming(incoming).await?
+            let server = serve_tcp(listener, settings, auth, routes, connections, shutdown)?;
+            Ok((addr.to_string(), server))
         }
         _ => bail!("invalid socket: {socket}"),
     }
+}
+
+// Serve requests on a socket passed in via systemd until shutdown.
+//
+// Access policies are taken from the configured listener for the same socket, if any.
+fn activate(
+    socket: systemd::Socket,
+    settings: &Settings,
+    routes: Routes,
+    connections: Connections,
+    shutdown: Shutdown,
+) -> Result<(String, BoxFuture<'static, Result<()>>)> {
+    let find_listener = |matches: &dyn Fn(&str) -> bool, addr: String| {
+        settings
+            .socket
+            .iter()
+            .find(|l| matches(&l.socket))
+            .cloned()
+            .unwrap_or_else(|| Listener::new(addr))
+    };
+
+    match socket {
+        systemd::Socket::Unix(listener) => {
+            let addr = listener.local_addr()?;
+            let path = addr
+                .as_pathname()
+                .and_then(|p| p.to_str())
+                .context(format!("unsupported activated socket: {addr:?}"))?
+                .to_string();
+            let auth = auth_layer(&find_listener(&|s| s == path, path.clone()), settings, true)?;
+            listener.set_nonblocking(true)?;
+            let listener = UnixListener::from_std(listener)?;
+            eprintln!("arcanist listening at: {path}");
+            // the socket file is managed by systemd so it's left in place
+            let server = serve_uds(listener, None, auth, routes, connections, shutdown);
+            Ok((path, server))
+        }
+        systemd::Socket::Tcp(listener) => {
+            let addr = listener.local_addr()?;
+            let matches = |s: &str| {
+                s.parse::<SocketAddr>()
+                    .map(|a| a == addr)
+                    .unwrap_or_default()
+            };
+            let auth = auth_layer(&find_listener(&matches, addr.to_string()), settings, false)?;
+            listener.set_nonblocking(true)?;
+            let listener = TcpListener::from_std(listener)?;
+            eprintln!("arcanist listening at: {addr}");
+            let server = serve_tcp(listener, settings, auth, routes, connections, shutdown)?;
+            Ok((addr.to_string(), server))
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = cmd().get_matches();
+    let load_config = !args.is_present("config-none");
+
+    // builds and syncs run in child processes re-executing the daemon
+    if let Some(pkg) = args.value_of("build") {
+        return worker::run_build(pkg, load_config);
+    } else if let Some(repo) = args.value_of("sync") {
+        return worker::run_sync(repo, load_config);
+    }
+
+    serve(args, load_config)
+}
+
+#[tokio::main]
+async fn serve(args: ArgMatches, load_config: bool) -> Result<()> {
+    let (settings, config) = load_settings(&args)?;
+    let activated = systemd::listen_fds()?;
+    let activated_sockets = !activated.is_empty();
+    let ready_fd = args.value_of("ready-fd").map(|s| s.parse().unwrap());
+
+    // support changing the log filter when reloading settings
+    let log_filter = logging::init(&settings)?;
+
+    let installed = Database::new(config.path.data.join("installed"));
+    let store = Store::new(config.path.data.join("jobs"));
+    let metrics = Metrics::new().context("failed creating metrics")?;
+    let jobs = JobManager::new(
+        settings.jobs,
+        config.path.data.join("logs"),
+        store,
+        metrics.clone(),
+    )
+    .context("failed loading job history")?;
+    let jobs = Arc::new(jobs);
+    let connections = Connections::default();
+
+    // exit after having no connections or running jobs for the idle timeout, if enabled
+    let idle = match settings.idle_timeout {
+        0 => future::pending().boxed(),
+        secs => idle::wait(connections.clone(), jobs.clone(), Duration::from_secs(secs)).boxed(),
+    };
+    let shutdown_request = Arc::new(Notify::new());
+
+    // report not serving until all listeners are ready
+    let (reporter, health_service) = tonic_health::server::health_reporter();
+    let health = Health::new(reporter);
+    health.not_serving().await;
+
+    let settings = Arc::new(RwLock::new(settings));
+    let repos = Arc::new(Repos::new(config));
+    let reloader = Arc::new(Reloader::new(
+        args,
+        settings.clone(),
+        repos.clone(),
+        log_filter,
+        health.clone(),
+    ));
+    let worker = Worker::new(load_config)?;
+    let service = ArcanistService {
+        settings: settings.clone(),
+        repos,
+        installed: Arc::new(RwLock::new(installed)),
+        worker: worker.clone(),
+        jobs: jobs.clone(),
+        shutdown: shutdown_request.clone(),
+        reloader: reloader.clone(),
+    };
+
+    // reload settings and pkgcraft config on SIGHUP
+    let mut sighup = signal(SignalKind::hangup()).context("failed registering SIGHUP handler")?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            match reloader.reload().await {
+                Ok(changes) if changes.is_empty() => info!("reloaded config: no changes"),
+                Ok(changes) => info!("reloaded config: {}", changes.join(", ")),
+                Err(e) => error!("config reload rejected: {e:#}"),
+            }
+        }
+    });
+    let service = arcanist::Server::new(service);
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(arcanist::proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(
+            tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET,
+        )
+        .build()
+        .context("failed creating reflection service")?;
+    let metrics_layer = MetricsLayer::new(metrics.clone());
+    let routes: Routes = Arc::new(move |server: Server, auth: AuthLayer| {
+        server
+            .layer(TraceLayer)
+            .layer(metrics_layer.clone())
+            .layer(auth)
+            .add_service(health_service.clone())
+            .add_service(reflection.clone())
+            .add_service(service.clone())
+    });
+
+    let shutdown = shutdown_signal(shutdown_request, idle).boxed().shared();
+
+    // bind all listeners before serving so failures occur on startup, preferring sockets passed
+    // in via systemd over configured sockets
+    let (mut sockets, mut servers, mut metrics_addr) = (vec![], vec![], None);
+    // socket files are removed if startup fails so they don't block future instances
+    let mut socket_files = SocketFiles::default();
+    {
+        let settings = settings.read().await;
+        if !activated_sockets {
+            for listener in &settings.socket {
+                let (routes, connections) = (routes.clone(), connections.clone());
+                let (socket, server) =
+                    bind(listener, &settings, routes, connections, shutdown.clone()).await?;
+                if socket.starts_with('/') {
+                    socket_files.0.push(socket.clone());
+                }
+                sockets.push(socket);
+                servers.push(server);
+            }
+        } else {
+            for socket in activated {
+                let (routes, connections) = (routes.clone(), connections.clone());
+                let (socket, server) =
+                    activate(socket, &settings, routes, connections, shutdown.clone())?;
+                sockets.push(socket);
+                servers.push(server);
+            }
+        }
+
+        // metrics are served over plain HTTP separately from RPC listeners, if enabled
+        if !settings.metrics.is_empty() {
+            let addr = settings
+                .metrics
+                .parse()
+                .context(format!("invalid metrics socket: {}", settings.metrics))?;
+            let (addr, server) =
+                metrics::serve(addr, metrics, connections, jobs.clone(), shutdown.clone())?;
+            eprintln!("arcanist serving metrics at: http://{addr}/metrics");
+            servers.push(server);
+            metrics_addr = Some(addr.to_string());
+        }
+    }
+
+    // unix domain sockets bound by the daemon are removed on exit
+    let owned_sockets = socket_files.0.clone();
+
+    // stop accepting requests on shutdown while concurrently draining jobs since open streams
+    // aren't closed until their jobs finish
+    let drain = tokio::spawn({
+        let (shutdown, settings, health) = (shutdown.clone(), settings.clone(), health.clone());
+        async move {
+            shutdown.await;
+            health.stopping().await;
+            systemd::notify(&[NotifyState::Stopping, NotifyState::Status("draining jobs")]);
+            let grace = Duration::from_secs(settings.read().await.shutdown_timeout);
+            if !jobs.shutdown(grace).await {
+                // jobs that failed to halt keep their streams and blocking tasks alive, so kill
+                // their child processes and exit without waiting on them
+                worker.kill();
+                for path in owned_sockets {
+                    fs::remove_file(path).unwrap_or_default();
+                }
+                error!("forcing exit with unhalted jobs");
+                process::exit(1);
+            }
+        }
+    });
+
+    health.serving().await;
+    if let Some(fd) = ready_fd {
+        report_ready(fd, sockets, metrics_addr)?;
+    }
+    // servers remove their own socket files on exit once started
+    socket_files.release();
+    systemd::notify(&[
+        NotifyState::Ready,
+        NotifyState::Status("accepting requests"),
+    ]);
+    future::try_join_all(servers).await?;
+
+    drain.await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_files() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        for name in ["a.sock", "b.sock"] {
+            fs::write(path(name), "").unwrap();
+        }
+
+        // files are removed when dropped
+        drop(SocketFiles(vec![path("a.sock")]));
+        assert!(!Path::new(&path("a.sock")).exists());
+
+        // but left in place once released
+        SocketFiles(vec![path("b.sock")]).release();
+        assert!(Path::new(&path("b.sock")).exists());
+    }
+
+    #[test]
+    fn test_auth_layer() {
+        let mut settings = Settings::default();
+        let listener = |socket: &str, access| Listener {
+            access,
+            ..Listener::new(socket)
+        };
+
+        // trusted access requires authenticated clients
+        let uds = listener("/run/arcanist.sock", Access::Trusted);
+        let tcp = listener("127.0.0.1:8080", Access::Trusted);
+        assert!(auth_layer(&uds, &settings, true).is_ok());
+        let err = auth_layer(&tcp, &settings, false).unwrap_err();
+        assert!(err.to_string().starts_with("trusted access requires TLS"));
+        settings.tls_client_ca = "/etc/arcanist/ca.pem".to_string();
+        assert!(auth_layer(&tcp, &settings, false).is_ok());
+
+        // other access policies are allowed on all sockets
+        let settings = Settings::default();
+        for access in [Access::Peer, Access::ReadOnly] {
+            let tcp = listener("127.0.0.1:8080", access);
+            assert!(auth_layer(&tcp, &settings, false).is_ok());
+        }
+    }
+}
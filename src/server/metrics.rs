@@ -0,0 +1,313 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as AnyhowContext, Result};
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::{make_service_fn, service_fn};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Request, Response, StatusCode};
+use tonic::transport::Body;
+use tonic::Code;
+use tower::{Layer, Service};
+
+use crate::idle::Connections;
+use crate::jobs::{JobManager, JobState};
+use crate::Shutdown;
+
+// RPC methods recorded by name along with whether they stream responses, all others are
+// recorded as unknown to bound label cardinality
+const METHODS: &[(&str, bool)] = &[
+    ("arcanist.Arcanist/Version", false),
+    ("arcanist.Arcanist/Shutdown", false),
+    ("arcanist.Arcanist/ReloadConfig", false),
+    ("arcanist.Arcanist/AddRepo", false),
+    ("arcanist.Arcanist/RemoveRepos", false),
+    ("arcanist.Arcanist/ListRepos", false),
+    ("arcanist.Arcanist/CreateRepo", false),
+    ("arcanist.Arcanist/SyncRepos", true),
+    ("arcanist.Arcanist/SearchPackages", true),
+    ("arcanist.Arcanist/AddPackages", true),
+    ("arcanist.Arcanist/RemovePackages", true),
+    ("arcanist.Arcanist/ListJobs", false),
+    ("arcanist.Arcanist/GetJob", false),
+    ("arcanist.Arcanist/CancelJob", false),
+    ("arcanist.Arcanist/StreamJobLog", true),
+    ("arcanist.Arcanist/JobHistory", false),
+    ("grpc.health.v1.Health/Check", false),
+    ("grpc.health.v1.Health/Watch", true),
+    (
+        "grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+        true,
+    ),
+];
+
+// Return the metrics label for a request path and whether the method streams responses.
+fn method(path: &str) -> (&'static str, bool) {
+    let path = path.trim_start_matches('/');
+    METHODS
+        .iter()
+        .find(|(name, _)| *name == path)
+        .copied()
+        .unwrap_or(("unknown", false))
+}
+
+/// Daemon metrics exported in the Prometheus text format.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    rpc_requests: IntCounterVec,
+    rpc_duration: HistogramVec,
+    connections: IntGauge,
+    jobs: IntGaugeVec,
+    builds: IntCounterVec,
+    build_duration: HistogramVec,
+    sync_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let rpc_requests = IntCounterVec::new(
+            Opts::new("arcanist_rpc_requests_total", "RPC requests handled"),
+            &["method", "code"],
+        )?;
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "arcanist_rpc_duration_seconds",
+                "unary RPC response latency",
+            ),
+            &["method"],
+        )?;
+        let connections = IntGauge::new("arcanist_connections", "open client connections")?;
+        let jobs = IntGaugeVec::new(
+            Opts::new("arcanist_jobs", "unfinished jobs by state"),
+            &["state"],
+        )?;
+        let builds = IntCounterVec::new(
+            Opts::new("arcanist_builds_total", "package builds by outcome"),
+            &["result"],
+        )?;
+        let build_duration = HistogramVec::new(
+            HistogramOpts::new("arcanist_build_duration_seconds", "package build duration")
+                .buckets(exponential_buckets(1.0, 2.0, 14)?),
+            &["result"],
+        )?;
+        let sync_duration = HistogramVec::new(
+            HistogramOpts::new("arcanist_repo_sync_duration_seconds", "repo sync duration")
+                .buckets(exponential_buckets(0.5, 2.0, 12)?),
+            &["repo", "result"],
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(rpc_requests.clone()))?;
+        registry.register(Box::new(rpc_duration.clone()))?;
+        registry.register(Box::new(connections.clone()))?;
+        registry.register(Box::new(jobs.clone()))?;
+        registry.register(Box::new(builds.clone()))?;
+        registry.register(Box::new(build_duration.clone()))?;
+        registry.register(Box::new(sync_duration.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            rpc_requests,
+            rpc_duration,
+            connections,
+            jobs,
+            builds,
+            build_duration,
+            sync_duration,
+        })
+    }
+
+    fn observe_rpc(&self, method: &str, code: Code, duration: Option<Duration>) {
+        let code = format!("{code:?}");
+        self.rpc_requests.with_label_values(&[method, &code]).inc();
+        if let Some(duration) = duration {
+            self.rpc_duration
+                .with_label_values(&[method])
+                .observe(duration.as_secs_f64());
+        }
+    }
+
+    /// Record a finished package build.
+    pub fn observe_build(&self, success: bool, duration: Duration) {
+        let result = outcome(success);
+        self.builds.with_label_values(&[result]).inc();
+        self.build_duration
+            .with_label_values(&[result])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record a finished repo sync.
+    pub fn observe_sync(&self, repo: &str, success: bool, duration: Duration) {
+        self.sync_duration
+            .with_label_values(&[repo, outcome(success)])
+            .observe(duration.as_secs_f64());
+    }
+
+    // Encode all metrics, updating gauges from the current daemon state.
+    fn encode(&self, connections: &Connections, jobs: &JobManager) -> Result<Vec<u8>> {
+        self.connections.set(connections.count() as i64);
+        for (label, state) in [("queued", JobState::Queued), ("running", JobState::Running)] {
+            self.jobs
+                .with_label_values(&[label])
+                .set(jobs.count(state) as i64);
+        }
+
+        let mut data = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut data)?;
+        Ok(data)
+    }
+}
+
+fn outcome(success: bool) -> &'static str {
+    match success {
+        true => "success",
+        false => "failure",
+    }
+}
+
+/// Serve metrics over HTTP at /metrics until shutdown, returning the bound address.
+pub fn serve(
+    addr: SocketAddr,
+    metrics: Metrics,
+    connections: Connections,
+    jobs: Arc<JobManager>,
+    shutdown: Shutdown,
+) -> Result<(SocketAddr, BoxFuture<'static, Result<()>>)> {
+    let make_service = make_service_fn(move |_| {
+        let (metrics, connections, jobs) = (metrics.clone(), connections.clone(), jobs.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<hyper::Body>| {
+                let response = match req.uri().path() {
+                    "/metrics" => match metrics.encode(&connections, &jobs) {
+                        Ok(data) => Response::builder()
+                            .header("content-type", TextEncoder::new().format_type())
+                            .body(hyper::Body::from(data)),
+                        Err(e) => Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(hyper::Body::from(format!("{e:#}"))),
+                    },
+                    _ => Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(hyper::Body::empty()),
+                };
+                async move { response }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&addr)
+        .context(format!("failed binding metrics socket: {addr}"))?
+        .serve(make_service);
+    let addr = server.local_addr();
+    let server = server
+        .with_graceful_shutdown(shutdown)
+        .map(|r| r.context("metrics server failed"));
+    Ok((addr, server.boxed()))
+}
+
+/// Layer recording RPC request counts and latencies.
+///
+/// Requests are recorded once their initial response is returned, so streaming RPCs are
+/// counted using the status they start with and are excluded from latencies as their duration
+/// depends on the amount of streamed data.
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Metrics) -> Self {
+        MetricsLayer { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Measured<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Measured {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Measured<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S> Service<Request<Body>> for Measured<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // gRPC request paths are in the form of /{package}.{service}/{method}
+        let (method, streaming) = method(req.uri().path());
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let result = response.await;
+            // Failures are returned as trailers-only responses with the status in the headers,
+            // otherwise the request succeeded or a stream failed after its initial response.
+            let code = match &result {
+                Ok(response) => response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|s| s.parse().ok())
+                    .map(Code::from_i32)
+                    .unwrap_or(Code::Ok),
+                Err(_) => Code::Unknown,
+            };
+            let duration = (!streaming).then(|| started.elapsed());
+            metrics.observe_rpc(method, code, duration);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method() {
+        assert_eq!(
+            method("/arcanist.Arcanist/Version"),
+            ("arcanist.Arcanist/Version", false)
+        );
+        assert_eq!(
+            method("/arcanist.Arcanist/SyncRepos"),
+            ("arcanist.Arcanist/SyncRepos", true)
+        );
+        assert_eq!(
+            method("/grpc.health.v1.Health/Check"),
+            ("grpc.health.v1.Health/Check", false)
+        );
+
+        // unknown paths share a label
+        for path in ["/", "/arcanist.Arcanist/Nonexistent", "/random/path", ""] {
+            assert_eq!(method(path), ("unknown", false));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use clap::ArgMatches;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::health::Health;
+use crate::logging::{self, FilterHandle};
+use crate::repos::Repos;
+use crate::settings::Settings;
+
+// settings that can only be changed by restarting
+const RESTART_SETTINGS: &[&str] = &[
+    "socket",
+    "socket_mode",
+    "socket_owner",
+    "socket_group",
+    "jobs",
+    "idle_timeout",
+    "tls_cert",
+    "tls_key",
+    "tls_client_ca",
+    "admin_group",
+    "metrics",
+    "log_format",
+    "log_file",
+    "log_rotate",
+    "log_max_size",
+    "log_keep",
+];
+
+// Describe the changes between settings, failing if any require a restart.
+fn settings_changes(old: &Settings, new: &Settings) -> Result<Vec<String>> {
+    let mut changes = vec![];
+    for (name, old, new) in old.changes(new) {
+        if RESTART_SETTINGS.contains(&name.as_str()) {
+            bail!("changing {name} requires a restart");
+        }
+        changes.push(format!("{name}: {old} -> {new}"));
+    }
+    Ok(changes)
+}
+
+/// Reloads settings and pkgcraft config for a running daemon.
+#[derive(Debug)]
+pub struct Reloader {
+    args: ArgMatches,
+    settings: Arc<RwLock<Settings>>,
+    repos: Arc<Repos>,
+    log_filter: FilterHandle,
+    health: Health,
+    lock: Mutex<()>,
+}
+
+impl Reloader {
+    pub fn new(
+        args: ArgMatches,
+        settings: Arc<RwLock<Settings>>,
+        repos: Arc<Repos>,
+        log_filter: FilterHandle,
+        health: Health,
+    ) -> Self {
+        Reloader {
+            args,
+            settings,
+            repos,
+            log_filter,
+            health,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Re-read the config files, returning descriptions of the applied changes.
+    ///
+    /// Command-line settings continue to override config file values. Reloads are rejected
+    /// as a whole if loading fails or if settings requiring a restart are changed. The daemon
+    /// is reported as not serving while the config is being loaded.
+    pub async fn reload(&self) -> Result<Vec<String>> {
+        // serialize reloads so changes are reported against the previous reload
+        let _guard = self.lock.lock().await;
+        self.health.not_serving().await;
+        let result = self.load().await;
+        self.health.serving().await;
+        result
+    }
+
+    async fn load(&self) -> Result<Vec<String>> {
+        let (settings, config) = crate::load_settings(&self.args)?;
+
+        let mut changes = settings_changes(&*self.settings.read().await, &settings)?;
+        let filter = logging::filter(&settings)?;
+
+        let old_repos: HashSet<_> = self
+            .repos
+            .snapshot()
+            .repos
+            .iter()
+            .map(|(id, _)| id.to_string())
+            .collect();
+        let new_repos: HashSet<_> = config.repos.iter().map(|(id, _)| id.to_string()).collect();
+        let mut added: Vec<_> = new_repos.difference(&old_repos).collect();
+        let mut removed: Vec<_> = old_repos.difference(&new_repos).collect();
+        added.sort();
+        removed.sort();
+        changes.extend(added.into_iter().map(|id| format!("added repo: {id}")));
+        changes.extend(removed.into_iter().map(|id| format!("removed repo: {id}")));
+
+        self.log_filter.reload(filter)?;
+        *self.settings.write().await = settings;
+        self.repos.replace(config).await;
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::Listener;
+
+    use super::*;
+
+    #[test]
+    fn test_settings_changes() {
+        let old = Settings::default();
+
+        // no changes
+        assert!(settings_changes(&old, &Settings::default())
+            .unwrap()
+            .is_empty());
+
+        // reloadable settings
+        let new = Settings {
+            verbosity: 1,
+            log_filter: "arcanist=debug".to_string(),
+            ..Default::default()
+        };
+        let mut changes = settings_changes(&old, &new).unwrap();
+        changes.sort();
+        assert_eq!(
+            changes,
+            [
+                "log_filter: \"\" -> \"arcanist=debug\"",
+                "verbosity: 0 -> 1"
+            ]
+        );
+
+        // settings requiring a restart reject the entire reload
+        for new in [
+            Settings {
+                socket: vec![Listener::new("/run/arcanist.sock")],
+                ..Default::default()
+            },
+            Settings {
+                verbosity: 1,
+                jobs: 4,
+                ..Default::default()
+            },
+        ] {
+            let err = settings_changes(&old, &new).unwrap_err().to_string();
+            assert!(err.ends_with("requires a restart"), "{err}");
+        }
+        let new = Settings {
+            log_keep: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            settings_changes(&old, &new).unwrap_err().to_string(),
+            "changing log_keep requires a restart"
+        );
+    }
+}
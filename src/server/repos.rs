@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+use pkgcraft::config::Config as PkgcraftConfig;
+use pkgcraft::repo::Repository;
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Repo configuration shared between requests via copy-on-write snapshots.
+///
+/// Readers grab the current snapshot without waiting on writers, config changes are applied
+/// to a copy that replaces the current snapshot once complete, and syncs are serialized per
+/// repo so different repos can sync concurrently.
+#[derive(Debug)]
+pub struct Repos {
+    snapshot: RwLock<Arc<PkgcraftConfig>>,
+    writer: AsyncMutex<()>,
+    sync_locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl Repos {
+    pub fn new(config: PkgcraftConfig) -> Self {
+        Repos {
+            snapshot: RwLock::new(Arc::new(config)),
+            writer: AsyncMutex::new(()),
+            sync_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Replace the current snapshot, dropping unused sync locks for repos no longer configured.
+    fn set(&self, config: PkgcraftConfig) {
+        let ids: HashSet<_> = config.repos.iter().map(|(id, _)| id.to_string()).collect();
+        *self.snapshot.write().unwrap() = Arc::new(config);
+        self.sync_locks
+            .lock()
+            .unwrap()
+            .retain(|id, lock| ids.contains(id) || Arc::strong_count(lock) > 1);
+    }
+
+    /// Return the current config snapshot.
+    pub fn snapshot(&self) -> Arc<PkgcraftConfig> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// Apply a change to a copy of the current config, replacing the snapshot on success.
+    ///
+    /// Changes are applied while holding the writer lock so any checks they make against the
+    /// config can't be invalidated by concurrent changes.
+    pub async fn update<F, T, E>(&self, func: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut PkgcraftConfig) -> Result<T, E>,
+    {
+        // serialize writers so concurrent changes aren't lost
+        let _guard = self.writer.lock().await;
+        let mut config = PkgcraftConfig::clone(&self.snapshot());
+        let value = func(&mut config)?;
+        self.set(config);
+        Ok(value)
+    }
+
+    /// Replace the current config, e.g. after reloading it from disk.
+    pub async fn replace(&self, config: PkgcraftConfig) {
+        let _guard = self.writer.lock().await;
+        self.set(config);
+    }
+
+    /// Reload synced repos from their paths so their changes are visible to new readers.
+    ///
+    /// Only the given repos are replaced, leaving the rest of the config untouched.
+    pub async fn refresh(&self, ids: &[String]) -> pkgcraft::Result<()> {
+        self.update(|config| {
+            let repos: Vec<_> = config
+                .repos
+                .iter()
+                .filter(|(id, _)| ids.iter().any(|s| s == id.as_str()))
+                .map(|(id, repo)| {
+                    let priority = repo.repo_config().priority;
+                    (id.to_string(), priority, repo.path().to_string())
+                })
+                .collect();
+
+            // repos are recreated using their existing priorities and paths
+            for (id, priority, path) in repos {
+                config.del_repos(&[id.clone()], false)?;
+                config.add_repo_path(&id, priority, &path)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Return the lock that must be held while syncing a repo.
+    pub fn sync_lock(&self, id: &str) -> Arc<AsyncMutex<()>> {
+        self.sync_locks
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+}
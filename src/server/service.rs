@@ -1,150 +1,810 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+use anyhow::bail;
+use futures::future::join_all;
 use pkgcraft::config::Config as PkgcraftConfig;
+use pkgcraft::restrict::{self, Restrict};
 use pkgcraft::{repo::Repository, Error};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::runtime::Handle;
 use tokio::sync::mpsc;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
+use tokio::task;
+use tokio::time::sleep;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
+use tracing::{info, warn};
 
+use arcanist::{ErrorDetails, Reason};
+
+use crate::build;
+use crate::convert;
+use crate::installed::{Database, InstalledPkg};
+use crate::jobs::{BuildRecord, Closed, JobHandle, JobKind, JobManager};
+use crate::reload::Reloader;
+use crate::repos::Repos;
 use crate::settings::Settings;
+use crate::worker::Worker;
 
 use arcanist::proto::{
-    arcanist_server::Arcanist, AddRepoRequest, ListRequest, ListResponse, StringRequest,
-    StringResponse,
+    arcanist_server::Arcanist, build_event::Kind, sync_event, unmerge_event, AddRepoRequest,
+    BuildEvent, CreateRepoRequest, Job, JobHistoryRequest, JobId, JobList, JobLogChunk,
+    JobLogRequest, ListJobsRequest, ListReposRequest, Package, PackageTargets, ReloadConfigRequest,
+    ReloadConfigResponse, Repo, RepoIds, RepoList, ShutdownRequest, ShutdownResponse, SyncEvent,
+    UnmergeEvent, VersionRequest, VersionResponse,
 };
 
+// interval between checks for new output when following job logs
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
 #[derive(Debug)]
 pub struct ArcanistService {
-    pub settings: Settings,
-    pub config: Arc<RwLock<PkgcraftConfig>>,
+    pub settings: Arc<RwLock<Settings>>,
+    pub repos: Arc<Repos>,
+    pub installed: Arc<RwLock<Database>>,
+    pub worker: Worker,
+    pub jobs: Arc<JobManager>,
+    pub shutdown: Arc<Notify>,
+    pub reloader: Arc<Reloader>,
 }
 
-#[tonic::async_trait]
-impl Arcanist for ArcanistService {
-    async fn add_repo(
-        &self,
-        request: Request<AddRepoRequest>,
-    ) -> Result<Response<StringResponse>, Status> {
-        let req = request.into_inner();
-        let config = &mut self.config.write().await;
-        match config.add_repo_uri(&req.name, 0, &req.uri) {
-            Err(Error::Config(e)) => Err(Status::failed_precondition(&e)),
-            Err(e) => Err(Status::internal(format!("{e}"))),
-            Ok(_) => {
-                let reply = StringResponse { data: req.name };
-                Ok(Response::new(reply))
+// Parse package targets into restrictions, failing on the first invalid target.
+fn parse_targets(targets: &[String]) -> Result<Vec<Restrict>, Status> {
+    let mut restricts = Vec::new();
+    for target in targets.iter() {
+        match restrict::parse::dep(target) {
+            Ok(r) => restricts.push(r),
+            Err(e) => return Err(Reason::InvalidTarget.status(format!("{e}"))),
+        }
+    }
+    Ok(restricts)
+}
+
+// Package removal failures caused by the requested targets.
+#[derive(Debug, thiserror::Error)]
+enum RemoveError {
+    #[error("no installed packages match: {0}")]
+    NotInstalled(String),
+    #[error("packages still required: {0}")]
+    Required(String),
+}
+
+// Create a build progress event for a job.
+fn build_event(
+    job: &JobHandle,
+    kind: Kind,
+    package: Option<Package>,
+    index: usize,
+    total: usize,
+) -> BuildEvent {
+    let mut event = BuildEvent {
+        package,
+        index: index as u32,
+        total: total as u32,
+        job: job.id(),
+        ..Default::default()
+    };
+    event.set_kind(kind);
+    event
+}
+
+// Resolve, build, and install packages for a job, sending progress events to its client.
+//
+// Jobs keep running if their client disconnects so event send failures are ignored.
+fn build_pkgs(
+    job: &JobHandle,
+    worker: &Worker,
+    config: &PkgcraftConfig,
+    db: &mut Database,
+    root: &Path,
+    targets: &[String],
+    events: &mpsc::Sender<Result<BuildEvent, Status>>,
+) -> anyhow::Result<()> {
+    let send = |kind: Kind, package: Option<Package>, index: usize, total: usize| {
+        let event = build_event(job, kind, package, index, total);
+        events.blocking_send(Ok(event)).unwrap_or_default();
+    };
+
+    job.check()?;
+    let pkgs = build::resolve(config, db, targets)?;
+    let total = pkgs.len();
+    job.log(format!(">>> resolved {total} package(s)"));
+    send(Kind::Resolved, None, 0, total);
+
+    let log = job.log_file()?;
+    let runtime = Handle::current();
+    for (i, pkg) in pkgs.iter().enumerate() {
+        job.check()?;
+        let package = convert::pkg(pkg);
+        job.log(format!(">>> ({}/{total}) building {package}", i + 1));
+        send(Kind::Building, Some(package.clone()), i + 1, total);
+        let image = tempfile::Builder::new().prefix("arcanist.").tempdir()?;
+        let started = SystemTime::now();
+        // builds are waited on asynchronously so they can be killed when cancelled
+        let result = runtime
+            .block_on(worker.build(job, pkg, image.path(), &log))
+            .and_then(|_| build::merge(image.path(), root))
+            .and_then(|files| {
+                db.add(&InstalledPkg::new(pkg, files.clone()), root)?;
+                Ok(files)
+            });
+        job.record_build(BuildRecord::new(&package, started, &result));
+        result?;
+        job.log(format!(">>> ({}/{total}) installed {package}", i + 1));
+        send(Kind::Installed, Some(package), i + 1, total);
+    }
+
+    Ok(())
+}
+
+// Unmerge installed packages for a job, sending progress events to its client.
+fn remove_pkgs(
+    job: &JobHandle,
+    db: &mut Database,
+    root: &Path,
+    targets: &[String],
+    restricts: &[Restrict],
+    events: &mpsc::Sender<Result<UnmergeEvent, Status>>,
+) -> anyhow::Result<()> {
+    job.check()?;
+    let pkgs = db.matches(restricts)?;
+    if pkgs.is_empty() {
+        bail!(RemoveError::NotInstalled(targets.join(", ")));
+    }
+
+    // refuse to break the dependencies of remaining packages
+    let rdeps = db.rdeps(&pkgs)?;
+    if !rdeps.is_empty() {
+        let required: Vec<_> = rdeps
+            .iter()
+            .map(|(pkg, dep)| format!("{pkg} requires {dep}"))
+            .collect();
+        bail!(RemoveError::Required(required.join(", ")));
+    }
+
+    for pkg in pkgs {
+        job.check()?;
+        let files = db.unmerge(&pkg, root)?;
+        job.log(format!(">>> unmerged {pkg}: {} file(s)", files.len()));
+        let mut event = UnmergeEvent {
+            package: Some(convert::installed_pkg(&pkg)?),
+            files: files
+                .iter()
+                .map(|f| f.to_string_lossy().into_owned())
+                .collect(),
+            job: job.id(),
+            ..Default::default()
+        };
+        event.set_kind(unmerge_event::Kind::Unmerged);
+        events.blocking_send(Ok(event)).unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+// Send a job's log output from the requested offset, optionally following it until the job
+// finishes.
+async fn tail_log(
+    jobs: &JobManager,
+    req: &JobLogRequest,
+    tx: &mpsc::Sender<Result<JobLogChunk, Status>>,
+) -> io::Result<()> {
+    let path = jobs.log_path(req.id);
+    let mut offset = req.offset;
+    let mut file: Option<File> = None;
+    let mut buf = vec![0; 8192];
+
+    loop {
+        // check status before reading so trailing output isn't missed
+        let finished = jobs
+            .get(req.id)
+            .map(|job| job.state.is_finished())
+            .unwrap_or(true);
+
+        // queued jobs may not have created their log yet
+        if file.is_none() {
+            if let Ok(mut f) = File::open(&path).await {
+                f.seek(SeekFrom::Start(offset)).await?;
+                file = Some(f);
             }
         }
+
+        if let Some(f) = file.as_mut() {
+            loop {
+                let n = f.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                let chunk = JobLogChunk {
+                    data: buf[..n].to_vec(),
+                    offset,
+                };
+                offset += n as u64;
+                // stop if the client disconnected
+                if tx.send(Ok(chunk)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+
+        if finished || !req.follow {
+            return Ok(());
+        }
+        sleep(LOG_POLL_INTERVAL).await;
     }
+}
 
-    async fn remove_repos(
-        &self,
-        request: Request<ListRequest>,
-    ) -> Result<Response<ListResponse>, Status> {
-        let req = request.into_inner();
-        let config = &mut self.config.write().await;
-        match config.del_repos(&req.data, true) {
-            Err(Error::Config(e)) => Err(Status::failed_precondition(&e)),
-            Err(e) => Err(Status::internal(format!("{e}"))),
-            Ok(_) => {
-                let reply = ListResponse { data: req.data };
-                Ok(Response::new(reply))
+// Create a sync progress event for a job.
+fn sync_event(job: &JobHandle, kind: sync_event::Kind, repo: &str, message: String) -> SyncEvent {
+    let mut event = SyncEvent {
+        repo: repo.to_string(),
+        message,
+        job: job.id(),
+        ..Default::default()
+    };
+    event.set_kind(kind);
+    event
+}
+
+// Forward each line of a sync's output to the job log and its client as progress events,
+// returning the last line.
+async fn forward_output<R: AsyncRead + Unpin>(
+    job: &JobHandle,
+    id: &str,
+    output: Option<R>,
+    events: &mpsc::Sender<Result<SyncEvent, Status>>,
+) -> Option<String> {
+    let mut last = None;
+    if let Some(output) = output {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            job.log(&line);
+            let event = sync_event(job, sync_event::Kind::Progress, id, line.clone());
+            events.send(Ok(event)).await.unwrap_or_default();
+            last = Some(line);
+        }
+    }
+    last
+}
+
+// Sync a repo in a child process, forwarding its output to the client and killing it if the job
+// is cancelled.
+async fn sync_repo(
+    job: &JobHandle,
+    worker: &Worker,
+    id: &str,
+    events: &mpsc::Sender<Result<SyncEvent, Status>>,
+) -> anyhow::Result<()> {
+    let mut child = worker.sync(id)?;
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    let (_, error, status) = tokio::join!(
+        forward_output(job, id, stdout, events),
+        forward_output(job, id, stderr, events),
+        worker.wait(job, &mut child),
+    );
+    let status = status?;
+    if !status.success() {
+        // failures are reported on the last line of the sync's error output
+        match error {
+            Some(e) => bail!("{}", e.trim_start_matches("Error: ")),
+            None => bail!("sync failed: {status}"),
+        }
+    }
+    Ok(())
+}
+
+// Sync repos for a job, defaulting to all repos when none are specified.
+//
+// Each repo is synced concurrently with per-repo events sent to the client, failing the job
+// if any repo fails without affecting the others.
+async fn sync(
+    job: &JobHandle,
+    repos: &Repos,
+    worker: &Worker,
+    ids: Vec<String>,
+    events: &mpsc::Sender<Result<SyncEvent, Status>>,
+) -> anyhow::Result<()> {
+    use sync_event::Kind;
+
+    job.check()?;
+    let config = repos.snapshot();
+    let ids: Vec<String> = match ids.is_empty() {
+        true => config.repos.iter().map(|(id, _)| id.to_string()).collect(),
+        false => ids,
+    };
+
+    let send = |kind, id: &str, msg: String| {
+        let tx = events.clone();
+        let event = sync_event(job, kind, id, msg);
+        async move { tx.send(Ok(event)).await.unwrap_or_default() }
+    };
+
+    let syncs = ids.into_iter().map(|id| {
+        let lock = repos.sync_lock(&id);
+        async move {
+            // wait for any running sync of the same repo to complete
+            let _guard = match lock.clone().try_lock_owned() {
+                Ok(guard) => guard,
+                Err(_) => {
+                    let msg = "waiting for running sync".to_string();
+                    send(Kind::Progress, &id, msg).await;
+                    lock.lock_owned().await
+                }
+            };
+
+            if job.is_cancelled() {
+                return Err(format!("{id}: job cancelled"));
+            }
+
+            job.log(format!(">>> syncing repo: {id}"));
+            send(Kind::Started, &id, String::new()).await;
+            let started = Instant::now();
+            let result = sync_repo(job, worker, &id, events)
+                .await
+                .map_err(|e| format!("{e:#}"));
+            job.record_sync(&id, started, result.is_ok());
+
+            match result {
+                Ok(_) => {
+                    job.log(format!(">>> synced repo: {id}"));
+                    send(Kind::Finished, &id, String::new()).await;
+                    Ok(id)
+                }
+                Err(e) => {
+                    job.log(format!(">>> failed syncing repo: {id}: {e}"));
+                    send(Kind::Failed, &id, e.clone()).await;
+                    Err(format!("{id}: {e}"))
+                }
             }
         }
+    });
+
+    let results = join_all(syncs).await;
+
+    // repos are loaded when configured so synced changes require refreshing them
+    let synced: Vec<_> = results
+        .iter()
+        .filter_map(|r| r.as_ref().ok().cloned())
+        .collect();
+    if !synced.is_empty() {
+        if let Err(e) = repos.refresh(&synced).await {
+            job.log(format!(">>> failed refreshing repos: {e}"));
+            warn!("failed refreshing repos: {e}");
+        }
+    }
+
+    let failed: Vec<_> = results.into_iter().filter_map(|r| r.err()).collect();
+    if !failed.is_empty() {
+        bail!("failed syncing repos: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+// Convert a job failure into the status returned to its client.
+fn job_status(job: &JobHandle, reason: Reason, e: &anyhow::Error) -> Status {
+    match job.is_cancelled() {
+        true => ErrorDetails::new(Reason::JobCancelled)
+            .with("job", job.id())
+            .status(format!("job {} cancelled", job.id())),
+        false => ErrorDetails::new(reason)
+            .with("job", job.id())
+            .status(format!("{e:#}")),
+    }
+}
+
+// Convert a repo config update failure into a status.
+fn config_status(e: Error) -> Status {
+    match e {
+        Error::Config(e) => Reason::RepoConfig.status(e),
+        e => Reason::Internal.status(format!("{e}")),
+    }
+}
+
+// Convert a job submission failure into a status.
+fn spawn_status(e: anyhow::Error) -> Status {
+    match e.is::<Closed>() {
+        true => Reason::ShuttingDown.status(format!("{e}")),
+        false => Reason::Internal.status(format!("{e:#}")),
+    }
+}
+
+// Fail if a repo is already configured.
+fn check_new_repo(config: &PkgcraftConfig, name: &str) -> Result<(), Status> {
+    match config.repos.iter().any(|(id, _)| id.as_str() == name) {
+        true => Err(ErrorDetails::new(Reason::RepoExists)
+            .with("repo", name)
+            .status(format!("existing repo: {name}"))),
+        false => Ok(()),
+    }
+}
+
+// Fail if any of the given repos aren't configured.
+fn check_repos(config: &PkgcraftConfig, names: &[String]) -> Result<(), Status> {
+    for name in names {
+        find_repo(config, name)?;
+    }
+    Ok(())
+}
+
+// Create the status returned for unknown jobs.
+fn job_not_found(id: u64) -> Status {
+    ErrorDetails::new(Reason::JobNotFound)
+        .with("job", id)
+        .status(format!("unknown job: {id}"))
+}
+
+// Return the protobuf representation of a configured repo.
+fn find_repo(config: &PkgcraftConfig, name: &str) -> Result<Repo, Status> {
+    config
+        .repos
+        .iter()
+        .find(|(id, _)| id.as_str() == name)
+        .map(|(id, repo)| convert::repo(id, repo))
+        .ok_or_else(|| {
+            ErrorDetails::new(Reason::RepoNotFound)
+                .with("repo", name)
+                .status(format!("unknown repo: {name}"))
+        })
+}
+
+#[tonic::async_trait]
+impl Arcanist for ArcanistService {
+    async fn add_repo(&self, request: Request<AddRepoRequest>) -> Result<Response<Repo>, Status> {
+        let req = request.into_inner();
+        let reply = self
+            .repos
+            .update(|config| {
+                check_new_repo(config, &req.name)?;
+                config
+                    .add_repo_uri(&req.name, 0, &req.uri)
+                    .map_err(config_status)?;
+                find_repo(config, &req.name)
+            })
+            .await?;
+        Ok(Response::new(reply))
+    }
+
+    async fn remove_repos(&self, request: Request<RepoIds>) -> Result<Response<RepoIds>, Status> {
+        let req = request.into_inner();
+        self.repos
+            .update(|config| {
+                check_repos(config, &req.ids)?;
+                config.del_repos(&req.ids, true).map_err(config_status)
+            })
+            .await?;
+        let reply = RepoIds { ids: req.ids };
+        Ok(Response::new(reply))
     }
 
     async fn list_repos(
         &self,
-        _request: Request<StringRequest>,
-    ) -> Result<Response<ListResponse>, Status> {
-        let mut repos: Vec<String> = Vec::new();
-        let config = self.config.read().await;
-        for (id, repo) in config.repos.iter() {
-            repos.push(format!("{id}: {:?}", repo.path()));
-        }
-        let reply = ListResponse { data: repos };
+        _request: Request<ListReposRequest>,
+    ) -> Result<Response<RepoList>, Status> {
+        let repos = self
+            .repos
+            .snapshot()
+            .repos
+            .iter()
+            .map(|(id, repo)| convert::repo(id, repo))
+            .collect();
+        let reply = RepoList { repos };
         Ok(Response::new(reply))
     }
 
     async fn create_repo(
         &self,
-        request: Request<StringRequest>,
-    ) -> Result<Response<StringResponse>, Status> {
+        request: Request<CreateRepoRequest>,
+    ) -> Result<Response<Repo>, Status> {
         let req = request.into_inner();
-        let config = &mut self.config.write().await;
-        match config.create_repo(&req.data, 0) {
-            Err(Error::Config(e)) => Err(Status::failed_precondition(&e)),
-            Err(e) => Err(Status::internal(format!("{e}"))),
-            Ok(_) => {
-                let reply = StringResponse { data: req.data };
-                Ok(Response::new(reply))
-            }
-        }
+        let reply = self
+            .repos
+            .update(|config| {
+                check_new_repo(config, &req.name)?;
+                config.create_repo(&req.name, 0).map_err(config_status)?;
+                find_repo(config, &req.name)
+            })
+            .await?;
+        Ok(Response::new(reply))
     }
 
+    type SyncReposStream = ReceiverStream<Result<SyncEvent, Status>>;
+
     async fn sync_repos(
         &self,
-        request: Request<ListRequest>,
-    ) -> Result<Response<ListResponse>, Status> {
+        request: Request<RepoIds>,
+    ) -> Result<Response<Self::SyncReposStream>, Status> {
         let req = request.into_inner();
-        let config = &mut self.config.write().await;
-        match config.repos.sync(req.data.clone()) {
-            Err(Error::Config(e)) => Err(Status::failed_precondition(&e)),
-            Err(e) => Err(Status::internal(format!("{e}"))),
-            Ok(_) => {
-                let reply = ListResponse { data: req.data };
-                Ok(Response::new(reply))
-            }
-        }
+        check_repos(&self.repos.snapshot(), &req.ids)?;
+        let repos = self.repos.clone();
+        let worker = self.worker.clone();
+        let (tx, rx) = mpsc::channel(4);
+
+        self.jobs
+            .spawn(JobKind::Sync, req.ids.clone(), move |job| {
+                // report the job ID up front so queued jobs can be cancelled
+                let event = sync_event(&job, sync_event::Kind::Queued, "", String::new());
+                tx.try_send(Ok(event)).unwrap_or_default();
+
+                async move {
+                    let result = sync(&job, &repos, &worker, req.ids, &tx).await;
+                    // per-repo failures are also reported via events before the final status
+                    if let Err(e) = &result {
+                        let status = job_status(&job, Reason::SyncFailed, e);
+                        tx.send(Err(status)).await.unwrap_or_default();
+                    }
+                    result
+                }
+            })
+            .map_err(spawn_status)?;
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
-    type SearchPackagesStream = ReceiverStream<Result<StringResponse, Status>>;
+    type SearchPackagesStream = ReceiverStream<Result<Package, Status>>;
 
     async fn search_packages(
         &self,
-        request: Request<ListRequest>,
+        request: Request<PackageTargets>,
     ) -> Result<Response<Self::SearchPackagesStream>, Status> {
+        let restricts = parse_targets(&request.into_inner().targets)?;
+        let config = self.repos.snapshot();
         let (tx, rx) = mpsc::channel(4);
-        tokio::spawn(async move {
-            for pkg in request.into_inner().data.iter() {
-                tx.send(Ok(StringResponse {
-                    data: pkg.to_string(),
-                }))
-                .await
-                .unwrap();
+
+        // iterating over repos is blocking work so matches are sent as they're found
+        task::spawn_blocking(move || {
+            for restrict in restricts {
+                for repo in config.repos.iter().filter_map(|(_, r)| r.as_ebuild()) {
+                    for pkg in repo.iter_restrict(restrict.clone()) {
+                        // stop searching if the client disconnected
+                        if tx.blocking_send(Ok(convert::pkg(&pkg))).is_err() {
+                            return;
+                        }
+                    }
+                }
             }
         });
         Ok(Response::new(ReceiverStream::new(rx)))
     }
 
-    type AddPackagesStream = ReceiverStream<Result<StringResponse, Status>>;
+    type AddPackagesStream = ReceiverStream<Result<BuildEvent, Status>>;
 
     async fn add_packages(
         &self,
-        _request: Request<ListRequest>,
+        request: Request<PackageTargets>,
     ) -> Result<Response<Self::AddPackagesStream>, Status> {
-        todo!()
+        let req = request.into_inner();
+        // fail invalid targets up front instead of queuing a job for them
+        parse_targets(&req.targets)?;
+        let root = PathBuf::from(&self.settings.read().await.root);
+        let repos = self.repos.clone();
+        let installed = self.installed.clone();
+        let worker = self.worker.clone();
+        let (tx, rx) = mpsc::channel(4);
+
+        self.jobs
+            .spawn(JobKind::Build, req.targets.clone(), move |job| {
+                // report the job ID up front so queued jobs can be cancelled
+                let event = build_event(&job, Kind::Queued, None, 0, 0);
+                tx.try_send(Ok(event)).unwrap_or_default();
+
+                async move {
+                    let result = async {
+                        // avoid waiting on the database if cancelled while queued
+                        job.check()?;
+                        let config = repos.snapshot();
+                        let mut db = installed.write_owned().await;
+                        let (handle, events) = (job.clone(), tx.clone());
+
+                        // resolving and building packages is blocking work
+                        task::spawn_blocking(move || {
+                            let (targets, db) = (&req.targets, &mut db);
+                            build_pkgs(&handle, &worker, &config, db, &root, targets, &events)
+                        })
+                        .await?
+                    }
+                    .await;
+
+                    if let Err(e) = &result {
+                        let status = job_status(&job, Reason::BuildFailed, e);
+                        tx.send(Err(status)).await.unwrap_or_default();
+                    }
+                    result
+                }
+            })
+            .map_err(spawn_status)?;
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
-    type RemovePackagesStream = ReceiverStream<Result<StringResponse, Status>>;
+    type RemovePackagesStream = ReceiverStream<Result<UnmergeEvent, Status>>;
 
     async fn remove_packages(
         &self,
-        _request: Request<ListRequest>,
+        request: Request<PackageTargets>,
     ) -> Result<Response<Self::RemovePackagesStream>, Status> {
-        todo!()
+        let req = request.into_inner();
+        let restricts = parse_targets(&req.targets)?;
+        let root = PathBuf::from(&self.settings.read().await.root);
+        let installed = self.installed.clone();
+        let (tx, rx) = mpsc::channel(4);
+
+        self.jobs
+            .spawn(JobKind::Remove, req.targets.clone(), move |job| {
+                // report the job ID up front so queued jobs can be cancelled
+                let mut event = UnmergeEvent {
+                    job: job.id(),
+                    ..Default::default()
+                };
+                event.set_kind(unmerge_event::Kind::Queued);
+                tx.try_send(Ok(event)).unwrap_or_default();
+
+                async move {
+                    let result = async {
+                        // avoid waiting on the database if cancelled while queued
+                        job.check()?;
+                        let mut db = installed.write_owned().await;
+                        let (handle, events) = (job.clone(), tx.clone());
+
+                        task::spawn_blocking(move || {
+                            let (targets, restricts) = (&req.targets, &restricts);
+                            remove_pkgs(&handle, &mut db, &root, targets, restricts, &events)
+                        })
+                        .await?
+                    }
+                    .await;
+
+                    if let Err(e) = &result {
+                        let reason = match e.downcast_ref::<RemoveError>() {
+                            Some(RemoveError::NotInstalled(_)) => Reason::NotInstalled,
+                            Some(RemoveError::Required(_)) => Reason::PackageRequired,
+                            None => Reason::RemoveFailed,
+                        };
+                        let status = job_status(&job, reason, e);
+                        tx.send(Err(status)).await.unwrap_or_default();
+                    }
+                    result
+                }
+            })
+            .map_err(spawn_status)?;
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn job_history(
+        &self,
+        request: Request<JobHistoryRequest>,
+    ) -> Result<Response<JobList>, Status> {
+        let limit = match request.into_inner().limit {
+            0 => None,
+            n => Some(n as usize),
+        };
+        match self.jobs.history(limit) {
+            Err(e) => Err(Reason::Internal.status(format!("{e:#}"))),
+            Ok(jobs) => {
+                let jobs = jobs.iter().map(convert::job).collect();
+                let reply = JobList { jobs };
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    async fn list_jobs(
+        &self,
+        _request: Request<ListJobsRequest>,
+    ) -> Result<Response<JobList>, Status> {
+        let jobs = self.jobs.list().iter().map(convert::job).collect();
+        let reply = JobList { jobs };
+        Ok(Response::new(reply))
+    }
+
+    async fn get_job(&self, request: Request<JobId>) -> Result<Response<Job>, Status> {
+        let id = request.into_inner().id;
+        match self.jobs.get(id) {
+            Some(job) => Ok(Response::new(convert::job(&job))),
+            None => Err(job_not_found(id)),
+        }
+    }
+
+    type StreamJobLogStream = ReceiverStream<Result<JobLogChunk, Status>>;
+
+    async fn stream_job_log(
+        &self,
+        request: Request<JobLogRequest>,
+    ) -> Result<Response<Self::StreamJobLogStream>, Status> {
+        let req = request.into_inner();
+        if self.jobs.get(req.id).is_none() {
+            return Err(job_not_found(req.id));
+        }
+
+        let jobs = self.jobs.clone();
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            if let Err(e) = tail_log(&jobs, &req, &tx).await {
+                tx.send(Err(Reason::Internal.status(format!("{e}"))))
+                    .await
+                    .unwrap_or_default();
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn cancel_job(&self, request: Request<JobId>) -> Result<Response<Job>, Status> {
+        let id = request.into_inner().id;
+        match self.jobs.cancel(id) {
+            Some(job) => Ok(Response::new(convert::job(&job))),
+            None => Err(job_not_found(id)),
+        }
+    }
+
+    async fn shutdown(
+        &self,
+        request: Request<ShutdownRequest>,
+    ) -> Result<Response<ShutdownResponse>, Status> {
+        let wait_for_jobs = request.into_inner().wait_for_jobs;
+        let (jobs, shutdown) = (self.jobs.clone(), self.shutdown.clone());
+
+        // run detached so disconnecting clients can't leave the job manager closed without the
+        // daemon exiting
+        let task = tokio::spawn(async move {
+            if wait_for_jobs {
+                jobs.close();
+                jobs.wait().await;
+            }
+
+            info!("shutdown requested");
+            shutdown.notify_one();
+        });
+
+        task.await
+            .map_err(|e| Reason::Internal.status(format!("{e}")))?;
+        Ok(Response::new(ShutdownResponse {}))
+    }
+
+    async fn reload_config(
+        &self,
+        _request: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponse>, Status> {
+        match self.reloader.reload().await {
+            Ok(changes) => Ok(Response::new(ReloadConfigResponse { changes })),
+            Err(e) => Err(Reason::InvalidConfig.status(format!("{e:#}"))),
+        }
     }
 
     async fn version(
         &self,
-        request: Request<StringRequest>,
-    ) -> Result<Response<StringResponse>, Status> {
+        request: Request<VersionRequest>,
+    ) -> Result<Response<VersionResponse>, Status> {
         let version = format!("{}-{}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
         let req = request.into_inner();
-        let reply = StringResponse {
-            data: format!("client: {}, server: {version}", req.data),
+        let reply = VersionResponse {
+            client: req.client,
+            server: version,
         };
         Ok(Response::new(reply))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() {
+        // valid targets
+        assert!(parse_targets(&[]).unwrap().is_empty());
+        let targets = ["cat/pkg".to_string(), ">=cat/pkg-1".to_string()];
+        assert_eq!(parse_targets(&targets).unwrap().len(), 2);
+
+        // a single invalid target fails the entire request
+        for target in ["=cat/pkg", ">cat/pkg-", "cat/pkg:"] {
+            let targets = ["cat/pkg".to_string(), target.to_string()];
+            let status = parse_targets(&targets).unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            let details = ErrorDetails::from_status(&status).unwrap();
+            assert_eq!(details.reason, Reason::InvalidTarget);
+        }
+    }
+}
@@ -3,13 +3,213 @@ use std::path::Path;
 use anyhow::{Context, Result};
 use config::{Config, Environment, File};
 use pkgcraft::config::Config as PkgcraftConfig;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// Access policy applied to clients of a listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    /// determine client roles from peer credentials or client certificates
+    Peer,
+    /// grant read-only access to all clients
+    ReadOnly,
+    /// grant admin access to all clients
+    Trusted,
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Self::Peer
+    }
+}
+
+/// Format of log output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// human-readable lines
+    Text,
+    /// one JSON object per event
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self::Text
+    }
+}
+
+/// Time-based rotation schedule for log files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self::Never
+    }
+}
+
+/// Socket to listen on along with its access policy.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "ListenerConfig")]
+pub struct Listener {
+    pub socket: String,
+    pub access: Access,
+    // overrides the global admin group for the listener
+    pub admin_group: String,
+}
+
+impl Listener {
+    /// Create a listener for a socket using the default access policy.
+    pub fn new<S: ToString>(socket: S) -> Self {
+        Listener {
+            socket: socket.to_string(),
+            access: Access::default(),
+            admin_group: String::new(),
+        }
+    }
+}
+
+// Listeners can be specified as either a bare socket or a table of options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenerConfig {
+    Socket(String),
+    Table {
+        socket: String,
+        #[serde(default)]
+        access: Access,
+        #[serde(default)]
+        admin_group: String,
+    },
+}
+
+// Sockets can be specified as either a comma-separated string or a list of listeners, the
+// former allowing multiple sockets to be set via environment variables.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenersConfig {
+    Sockets(String),
+    List(Vec<Listener>),
+}
+
+fn deserialize_listeners<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Listener>, D::Error> {
+    Ok(match ListenersConfig::deserialize(deserializer)? {
+        ListenersConfig::Sockets(sockets) => sockets
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(Listener::new)
+            .collect(),
+        ListenersConfig::List(listeners) => listeners,
+    })
+}
+
+impl From<ListenerConfig> for Listener {
+    fn from(config: ListenerConfig) -> Self {
+        match config {
+            ListenerConfig::Socket(socket) => Listener::new(socket),
+            ListenerConfig::Table {
+                socket,
+                access,
+                admin_group,
+            } => Listener {
+                socket,
+                access,
+                admin_group,
+            },
+        }
+    }
+}
+
+// Default to giving running jobs 30 seconds to finish on shutdown.
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+// Default to keeping five rotated log files.
+fn default_log_keep() -> usize {
+    5
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub debug: bool,
     pub verbosity: i32,
-    pub socket: String,
+    #[serde(deserialize_with = "deserialize_listeners")]
+    pub socket: Vec<Listener>,
+    pub socket_mode: String,
+    pub socket_owner: String,
+    pub socket_group: String,
+    pub root: String,
+    pub jobs: usize,
+    // seconds running jobs are given to finish on shutdown, with zero cancelling them immediately
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    pub idle_timeout: u64,
+    pub tls_cert: String,
+    pub tls_key: String,
+    pub tls_client_ca: String,
+    pub admin_group: String,
+    pub metrics: String,
+    // per-module filter directives in RUST_LOG format, e.g. "arcanist=debug,h2=warn"
+    pub log_filter: String,
+    pub log_format: LogFormat,
+    // log to the given file instead of stderr
+    pub log_file: String,
+    pub log_rotate: LogRotation,
+    // rotate log files once they surpass the given size in bytes
+    pub log_max_size: usize,
+    // number of rotated log files to keep
+    #[serde(default = "default_log_keep")]
+    pub log_keep: usize,
+}
+
+// Create the source for env variable overrides using a given prefix.
+//
+// Nested keys are separated by double underscores since setting names contain single ones,
+// e.g. ARCANIST_SOCKET_MODE sets socket_mode.
+fn environment(prefix: &str) -> Environment {
+    Environment::with_prefix(prefix)
+        .prefix_separator("_")
+        .separator("__")
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            debug: false,
+            verbosity: 0,
+            socket: vec![],
+            socket_mode: String::new(),
+            socket_owner: String::new(),
+            socket_group: String::new(),
+            root: String::new(),
+            jobs: 0,
+            shutdown_timeout: default_shutdown_timeout(),
+            idle_timeout: 0,
+            tls_cert: String::new(),
+            tls_key: String::new(),
+            tls_client_ca: String::new(),
+            admin_group: String::new(),
+            metrics: String::new(),
+            log_filter: String::new(),
+            log_format: LogFormat::default(),
+            log_file: String::new(),
+            log_rotate: LogRotation::default(),
+            log_max_size: 0,
+            log_keep: default_log_keep(),
+        }
+    }
 }
 
 impl Settings {
@@ -38,7 +238,7 @@ impl Settings {
         }
 
         // merge env variable overrides
-        s = s.add_source(Environment::with_prefix(&binary_upper).separator("_"));
+        s = s.add_source(environment(&binary_upper));
 
         // serialize to struct
         let s = s.build().context("failed building config")?;
@@ -46,4 +246,139 @@ impl Settings {
 
         Ok(settings)
     }
+
+    /// Return the names and old/new values of settings that differ from another instance.
+    pub fn changes(&self, other: &Self) -> Vec<(String, String, String)> {
+        let old = serde_json::to_value(self).unwrap_or_default();
+        let new = serde_json::to_value(other).unwrap_or_default();
+        let mut changes = vec![];
+        if let (Value::Object(old), Value::Object(new)) = (old, new) {
+            for (name, value) in new {
+                let old = old.get(&name).cloned().unwrap_or_default();
+                if old != value {
+                    changes.push((name, old.to_string(), value.to_string()));
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::FileFormat;
+
+    use super::*;
+
+    // Load settings from the given TOML config and env variables using a given prefix.
+    fn load(toml: &str, prefix: &str) -> Settings {
+        Config::builder()
+            .add_source(Config::try_from(&Settings::default()).unwrap())
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .add_source(environment(prefix))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_listeners() {
+        let prefix = "ARCANIST_TEST_LISTENERS";
+
+        // single socket
+        let settings = load(r#"socket = "/run/arcanist.sock""#, prefix);
+        assert_eq!(settings.socket, [Listener::new("/run/arcanist.sock")]);
+
+        // multiple listeners with per-listener access
+        let toml = r#"
+            socket = [
+                "/run/arcanist.sock",
+                { socket = "127.0.0.1:8080", access = "read-only" },
+                { socket = "/run/arcanist-admin.sock", access = "trusted", admin_group = "wheel" },
+            ]
+        "#;
+        let settings = load(toml, prefix);
+        assert_eq!(
+            settings.socket,
+            [
+                Listener::new("/run/arcanist.sock"),
+                Listener {
+                    socket: "127.0.0.1:8080".to_string(),
+                    access: Access::ReadOnly,
+                    admin_group: String::new(),
+                },
+                Listener {
+                    socket: "/run/arcanist-admin.sock".to_string(),
+                    access: Access::Trusted,
+                    admin_group: "wheel".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let prefix = "ARCANIST_TEST_ENV";
+        std::env::set_var(
+            format!("{prefix}_SOCKET"),
+            "/run/arcanist.sock, 127.0.0.1:8080",
+        );
+        std::env::set_var(format!("{prefix}_SOCKET_MODE"), "660");
+        std::env::set_var(format!("{prefix}_SHUTDOWN_TIMEOUT"), "0");
+
+        // env variables override config file values
+        let settings = load(r#"socket = "/tmp/arcanist.sock""#, prefix);
+        assert_eq!(
+            settings.socket,
+            [
+                Listener::new("/run/arcanist.sock"),
+                Listener::new("127.0.0.1:8080")
+            ]
+        );
+        assert_eq!(settings.socket_mode, "660");
+        assert_eq!(settings.shutdown_timeout, 0);
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = Settings::default();
+        assert!(old.changes(&Settings::default()).is_empty());
+
+        // changed values are reported in their serialized form
+        let new = Settings {
+            jobs: 2,
+            root: "/tmp/root".to_string(),
+            log_format: LogFormat::Json,
+            ..Default::default()
+        };
+        let mut changes = old.changes(&new);
+        changes.sort();
+        let expected: Vec<_> = [
+            ("jobs", "0", "2"),
+            ("log_format", r#""text""#, r#""json""#),
+            ("root", r#""""#, r#""/tmp/root""#),
+        ]
+        .iter()
+        .map(|(n, o, v)| (n.to_string(), o.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(changes, expected);
+
+        // nested values are compared as a whole
+        let new = Settings {
+            socket: vec![Listener::new("127.0.0.1:8080")],
+            ..Default::default()
+        };
+        let changes = old.changes(&new);
+        assert_eq!(changes.len(), 1);
+        let (name, old_value, new_value) = &changes[0];
+        assert_eq!(name, "socket");
+        assert_eq!(old_value, "[]");
+        assert!(new_value.contains("127.0.0.1:8080"));
+
+        // changes are reported relative to the instance being compared against
+        let (_, old_value, new_value) = &new.changes(&old)[0];
+        assert!(old_value.contains("127.0.0.1:8080"));
+        assert_eq!(new_value, "[]");
+    }
 }
@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tempfile::NamedTempFile;
+use tracing::warn;
+
+use crate::jobs::Job;
+
+/// On-disk store for job history, stored as one file per job.
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+}
+
+impl Store {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Store {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn job_path(&self, id: u64) -> PathBuf {
+        self.path.join(format!("{id}.json"))
+    }
+
+    /// Return all stored jobs ordered by ID, skipping unreadable records.
+    pub fn jobs(&self) -> Result<Vec<Job>> {
+        let mut jobs = Vec::new();
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(jobs),
+            Err(e) => return Err(e).context(format!("failed reading store: {:?}", self.path)),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().map(|s| s == "json").unwrap_or_default() {
+                // a single corrupt record shouldn't hide the rest of the history
+                match read_job(&path) {
+                    Ok(job) => jobs.push(job),
+                    Err(e) => warn!("skipping job record: {e:#}"),
+                }
+            }
+        }
+
+        jobs.sort_by_key(|job: &Job| job.id);
+        Ok(jobs)
+    }
+
+    /// Return the highest stored job ID using record file names, or zero if there are none.
+    pub fn last_id(&self) -> Result<u64> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).context(format!("failed reading store: {:?}", self.path)),
+        };
+
+        let mut last_id = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().map(|s| s == "json").unwrap_or_default() {
+                let id = path.file_stem().and_then(|s| s.to_str()?.parse().ok());
+                last_id = last_id.max(id.unwrap_or_default());
+            }
+        }
+        Ok(last_id)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Job>> {
+        let path = self.job_path(id);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(format!("failed reading: {path:?}")),
+        };
+        parse_job(&path, &data).map(Some)
+    }
+
+    /// Save a job record, replacing any previous version.
+    pub fn save(&self, job: &Job) -> Result<()> {
+        fs::create_dir_all(&self.path)
+            .context(format!("failed creating store dir: {:?}", self.path))?;
+        // write to a unique temporary file first so records are replaced atomically
+        let path = self.job_path(job.id);
+        let data = serde_json::to_vec(job)?;
+        let mut file = NamedTempFile::new_in(&self.path)
+            .context(format!("failed creating temporary file: {:?}", self.path))?;
+        file.write_all(&data)
+            .context(format!("failed writing: {:?}", file.path()))?;
+        file.persist(&path)
+            .context(format!("failed writing: {path:?}"))?;
+        Ok(())
+    }
+}
+
+// Load a job record from a file.
+fn read_job(path: &Path) -> Result<Job> {
+    let data = fs::read(path).context(format!("failed reading: {path:?}"))?;
+    parse_job(path, &data)
+}
+
+// Parse a job record read from a file.
+fn parse_job(path: &Path, data: &[u8]) -> Result<Job> {
+    serde_json::from_slice(data).context(format!("invalid job record: {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::jobs::{JobKind, JobState};
+
+    use super::*;
+
+    #[test]
+    fn test_store() {
+        let dir = tempdir().unwrap();
+        let store = Store::new(dir.path().join("jobs"));
+
+        // missing store dirs are treated as empty
+        assert!(store.jobs().unwrap().is_empty());
+        assert!(store.get(1).unwrap().is_none());
+
+        for id in [2, 1, 10] {
+            store.save(&Job::new(id, JobKind::Build, vec![])).unwrap();
+        }
+        let ids: Vec<_> = store.jobs().unwrap().iter().map(|j| j.id).collect();
+        assert_eq!(ids, [1, 2, 10]);
+
+        // saving replaces existing records without leaving temporary files behind
+        let mut job = Job::new(1, JobKind::Sync, vec!["repo".to_string()]);
+        job.state = JobState::Succeeded;
+        store.save(&job).unwrap();
+        let job = store.get(1).unwrap().unwrap();
+        assert_eq!(job.kind, JobKind::Sync);
+        assert_eq!(job.state, JobState::Succeeded);
+        assert_eq!(job.targets, ["repo"]);
+        assert_eq!(fs::read_dir(dir.path().join("jobs")).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_corrupt_records() {
+        let dir = tempdir().unwrap();
+        let store = Store::new(dir.path());
+        store.save(&Job::new(1, JobKind::Build, vec![])).unwrap();
+        store.save(&Job::new(3, JobKind::Build, vec![])).unwrap();
+        fs::write(dir.path().join("2.json"), "{").unwrap();
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        // corrupt records are skipped when listing jobs, but fail direct lookups
+        let ids: Vec<_> = store.jobs().unwrap().iter().map(|j| j.id).collect();
+        assert_eq!(ids, [1, 3]);
+        assert!(store.get(2).is_err());
+    }
+
+    #[test]
+    fn test_last_id() {
+        let dir = tempdir().unwrap();
+        let store = Store::new(dir.path().join("jobs"));
+        assert_eq!(store.last_id().unwrap(), 0);
+
+        store.save(&Job::new(2, JobKind::Build, vec![])).unwrap();
+        store.save(&Job::new(10, JobKind::Build, vec![])).unwrap();
+        assert_eq!(store.last_id().unwrap(), 10);
+
+        // corrupt records still count towards the last ID
+        fs::write(dir.path().join("jobs/11.json"), "{").unwrap();
+        fs::write(dir.path().join("jobs/12.txt"), "").unwrap();
+        assert_eq!(store.last_id().unwrap(), 11);
+    }
+}
@@ -0,0 +1,115 @@
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+
+use anyhow::{bail, Context, Result};
+use nix::sys::socket::{getsockname, AddressFamily, SockaddrLike, SockaddrStorage};
+use tracing::warn;
+
+pub use sd_notify::NotifyState;
+
+/// Listening socket passed in via systemd socket activation.
+#[derive(Debug)]
+pub enum Socket {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// Return the listening sockets passed in via systemd, if any.
+pub fn listen_fds() -> Result<Vec<Socket>> {
+    let fds = sd_notify::listen_fds().context("invalid socket activation environment")?;
+    sockets(fds)
+}
+
+// Convert passed in file descriptors into listening sockets, taking ownership of them.
+fn sockets<I: IntoIterator<Item = RawFd>>(fds: I) -> Result<Vec<Socket>> {
+    let mut sockets = vec![];
+    for fd in fds {
+        let addr: SockaddrStorage =
+            getsockname(fd).context(format!("invalid activated socket: fd {fd}"))?;
+        // systemd passes ownership of the listening sockets to the activated service
+        let socket = match addr.family() {
+            Some(AddressFamily::Unix) => Socket::Unix(unsafe { UnixListener::from_raw_fd(fd) }),
+            Some(AddressFamily::Inet | AddressFamily::Inet6) => {
+                Socket::Tcp(unsafe { TcpListener::from_raw_fd(fd) })
+            }
+            _ => bail!("unsupported activated socket type: fd {fd}"),
+        };
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// Send state notifications to systemd, doing nothing when not running under it.
+pub fn notify(state: &[NotifyState]) {
+    // notifications are informational so failures shouldn't be fatal
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("failed notifying systemd: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::process;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        // not socket activated
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        assert!(listen_fds().unwrap().is_empty());
+
+        // sockets passed to other processes are ignored
+        env::set_var("LISTEN_PID", (process::id() + 1).to_string());
+        env::set_var("LISTEN_FDS", "1");
+        assert!(listen_fds().unwrap().is_empty());
+
+        // no sockets passed
+        env::set_var("LISTEN_PID", process::id().to_string());
+        env::set_var("LISTEN_FDS", "0");
+        assert!(listen_fds().unwrap().is_empty());
+
+        // invalid values
+        for (pid, fds) in [("pid", "1"), ("", "1")] {
+            env::set_var("LISTEN_PID", pid);
+            env::set_var("LISTEN_FDS", fds);
+            assert!(listen_fds().is_err(), "LISTEN_PID={pid:?}");
+        }
+        let pid = process::id().to_string();
+        for fds in ["fds", "-1", ""] {
+            env::set_var("LISTEN_PID", &pid);
+            env::set_var("LISTEN_FDS", fds);
+            assert!(listen_fds().is_err(), "LISTEN_FDS={fds:?}");
+        }
+
+        // the environment is cleared so child processes don't inherit the sockets
+        assert!(env::var_os("LISTEN_PID").is_none());
+        assert!(env::var_os("LISTEN_FDS").is_none());
+    }
+
+    #[test]
+    fn test_sockets() {
+        let dir = tempdir().unwrap();
+        let uds = UnixListener::bind(dir.path().join("arcanist.sock")).unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp.local_addr().unwrap();
+
+        let fds = [uds.into_raw_fd(), tcp.into_raw_fd()];
+        let activated = sockets(fds).unwrap();
+        assert!(matches!(&activated[0], Socket::Unix(_)));
+        match &activated[1] {
+            Socket::Tcp(listener) => assert_eq!(listener.local_addr().unwrap(), tcp_addr),
+            s => panic!("unexpected socket: {s:?}"),
+        }
+
+        // non-socket descriptors are rejected
+        let file = tempfile::tempfile().unwrap();
+        assert!(sockets([file.as_raw_fd()]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::task::{Context, Poll};
+
+use arcanist::REQUEST_ID_KEY;
+use futures::future::BoxFuture;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Extensions, HeaderValue, Request, Response};
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::transport::Body;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+use tracing::{debug, info_span, warn, Instrument};
+use uuid::Uuid;
+
+use crate::uds::UdsConnectInfo;
+
+// maximum length of request IDs accepted from clients
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Describe the client of a request using its connection info.
+fn peer(extensions: &Extensions) -> String {
+    if let Some(info) = extensions.get::<UdsConnectInfo>() {
+        return match &info.peer_cred {
+            Some(cred) => match cred.pid() {
+                Some(pid) => format!("uid={} pid={pid}", cred.uid()),
+                None => format!("uid={}", cred.uid()),
+            },
+            None => "unknown".to_string(),
+        };
+    }
+
+    let addr = match extensions.get::<TlsConnectInfo<TcpConnectInfo>>() {
+        Some(info) => info.get_ref().remote_addr(),
+        None => extensions
+            .get::<TcpConnectInfo>()
+            .and_then(|info| info.remote_addr()),
+    };
+    addr.map(|a| a.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Layer running each request in a tracing span identified by a request ID.
+///
+/// IDs passed in by clients via request metadata are reused, otherwise a new ID is generated.
+/// Either way, the ID is returned to the client in the response metadata.
+#[derive(Debug, Default, Clone)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Traced<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Traced { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Traced<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for Traced<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_KEY)
+            .and_then(|v| v.to_str().ok())
+            .filter(|s| !s.is_empty() && s.len() <= MAX_REQUEST_ID_LEN)
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        // gRPC request paths are in the form of /{package}.{service}/{method}
+        let method = req.uri().path().trim_start_matches('/');
+        let span = info_span!(
+            "request",
+            id = %id,
+            peer = %peer(req.extensions()),
+            method = %method,
+        );
+
+        let response = span.in_scope(|| self.inner.call(req));
+        Box::pin(
+            async move {
+                let mut response = response.await?;
+                // failures are returned as trailers-only responses with the status in headers,
+                // stream failures occur after the response is returned
+                match Status::from_header_map(response.headers()) {
+                    Some(s) if s.code() != Code::Ok => warn!("failed: {}", s.message()),
+                    _ => debug!("finished"),
+                }
+                // IDs are validated as header values when accepted from clients
+                let value = HeaderValue::from_str(&id).expect("invalid request ID");
+                response.headers_mut().insert(REQUEST_ID_KEY, value);
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
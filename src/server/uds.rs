@@ -1,5 +1,6 @@
 use std::{
     fs,
+    os::unix::fs::PermissionsExt,
     os::unix::net::UnixStream as StdUnixStream,
     path::Path,
     pin::Pin,
@@ -7,7 +8,8 @@ use std::{
     task::{Context, Poll},
 };
 
-use anyhow::{bail, Context as AnyhowContext, Result};
+use anyhow::{bail, ensure, Context as AnyhowContext, Result};
+use nix::unistd::{chown, Gid, Group, Uid, User};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tonic::transport::server::Connected;
 
@@ -78,3 +80,123 @@ pub fn verify_socket_path<P: AsRef<Path>>(path: P) -> Result<()> {
 
     Ok(())
 }
+
+/// Ownership and permissions applied to UNIX domain sockets after binding.
+#[derive(Debug, Default)]
+pub struct SocketPerms {
+    mode: Option<u32>,
+    owner: Option<Uid>,
+    group: Option<Gid>,
+}
+
+impl SocketPerms {
+    /// Create socket permissions from an octal mode and owner and group names, skipping any
+    /// that are empty.
+    pub fn new(mode: &str, owner: &str, group: &str) -> Result<Self> {
+        let mut perms = SocketPerms::default();
+
+        if !mode.is_empty() {
+            let value = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .context(format!("invalid socket mode: {mode}"))?;
+            ensure!(value <= 0o7777, "invalid socket mode: {mode}");
+            perms.mode = Some(value);
+        }
+
+        if !owner.is_empty() {
+            let user = User::from_name(owner)
+                .context(format!("failed looking up user: {owner}"))?
+                .context(format!("unknown socket owner: {owner}"))?;
+            perms.owner = Some(user.uid);
+        }
+
+        if !group.is_empty() {
+            let group = Group::from_name(group)
+                .context(format!("failed looking up group: {group}"))?
+                .context(format!("unknown socket group: {group}"))?;
+            perms.group = Some(group.gid);
+        }
+
+        Ok(perms)
+    }
+
+    /// Apply the ownership and permissions to a socket path.
+    pub fn apply<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.owner.is_some() || self.group.is_some() {
+            chown(path, self.owner, self.group)
+                .context(format!("failed changing socket ownership: {path:?}"))?;
+        }
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .context(format!("failed changing socket permissions: {path:?}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_perms() {
+        // empty values are skipped
+        let perms = SocketPerms::new("", "", "").unwrap();
+        assert!(perms.mode.is_none() && perms.owner.is_none() && perms.group.is_none());
+
+        // octal modes with optional prefix
+        for mode in ["660", "0660", "0o660"] {
+            assert_eq!(SocketPerms::new(mode, "", "").unwrap().mode, Some(0o660));
+        }
+
+        // owners and groups are looked up by name
+        let perms = SocketPerms::new("", "root", "root").unwrap();
+        assert_eq!(perms.owner, Some(Uid::from_raw(0)));
+        assert_eq!(perms.group, Some(Gid::from_raw(0)));
+    }
+
+    #[test]
+    fn test_socket_perms_invalid() {
+        for (mode, owner, group, err) in [
+            ("abc", "", "", "invalid socket mode: abc"),
+            ("888", "", "", "invalid socket mode: 888"),
+            ("-660", "", "", "invalid socket mode: -660"),
+            ("17777", "", "", "invalid socket mode: 17777"),
+            (
+                "",
+                "nonexistent-user",
+                "",
+                "unknown socket owner: nonexistent-user",
+            ),
+            (
+                "",
+                "",
+                "nonexistent-group",
+                "unknown socket group: nonexistent-group",
+            ),
+        ] {
+            let result = SocketPerms::new(mode, owner, group);
+            assert_eq!(result.unwrap_err().to_string(), err);
+        }
+    }
+
+    #[test]
+    fn test_socket_perms_apply() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("arcanist.sock");
+        fs::write(&path, "").unwrap();
+
+        let perms = SocketPerms::new("0o640", "", "").unwrap();
+        perms.apply(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+
+        // missing paths fail
+        let err = perms.apply(dir.path().join("missing")).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("failed changing socket permissions"));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{setpgid, Pid};
+use pkgcraft::config::Config as PkgcraftConfig;
+use pkgcraft::pkg::{ebuild::Pkg, BuildablePackage, Package};
+use pkgcraft::restrict;
+use tokio::process::{Child, Command};
+
+use crate::build;
+use crate::jobs::JobHandle;
+
+/// Runs pkgcraft builds and repo syncs in child processes re-executing the daemon.
+///
+/// pkgcraft drives a process-wide bash instance and writes its output directly to stdout and
+/// stderr, so each operation runs in its own process with its own output and environment.
+/// Children are placed in their own process groups so they can be killed along with any
+/// processes they start.
+#[derive(Debug, Clone)]
+pub(crate) struct Worker {
+    exe: PathBuf,
+    load_config: bool,
+    // process group IDs of running children
+    children: Arc<Mutex<HashSet<i32>>>,
+}
+
+impl Worker {
+    pub(crate) fn new(load_config: bool) -> Result<Self> {
+        let exe = env::current_exe().context("failed locating daemon executable")?;
+        Ok(Worker {
+            exe,
+            load_config,
+            children: Default::default(),
+        })
+    }
+
+    // Create a command re-executing the daemon for a given operation.
+    fn daemon(&self, op: &str, value: &str) -> Command {
+        let mut cmd = command(&self.exe);
+        cmd.arg(op).arg(value);
+        if !self.load_config {
+            cmd.arg("--config-none");
+        }
+        cmd
+    }
+
+    fn track(&self, pid: Option<u32>) {
+        if let Some(pid) = pid {
+            self.children.lock().unwrap().insert(pid as i32);
+        }
+    }
+
+    fn untrack(&self, pid: Option<u32>) {
+        if let Some(pid) = pid {
+            self.children.lock().unwrap().remove(&(pid as i32));
+        }
+    }
+
+    // Start a child, tracking it until it's waited on.
+    fn start(&self, cmd: &mut Command) -> io::Result<Child> {
+        let child = cmd.spawn()?;
+        self.track(child.id());
+        Ok(child)
+    }
+
+    /// Build a package for a job, installing its files into the given image directory and
+    /// logging its output to the given file.
+    pub(crate) async fn build<P: AsRef<Path>>(
+        &self,
+        job: &JobHandle,
+        pkg: &Pkg<'_>,
+        image: P,
+        log: &File,
+    ) -> Result<()> {
+        let target = format!("={}::{}", pkg.atom(), pkg.repo().id());
+        // the build env pulls its install target from $D
+        let mut child = self
+            .start(
+                self.daemon("--build", &target)
+                    .env("D", image.as_ref())
+                    .stdout(log.try_clone()?)
+                    .stderr(log.try_clone()?),
+            )
+            .context(format!("{pkg}: failed starting build"))?;
+        let status = self.wait(job, &mut child).await?;
+        if !status.success() {
+            bail!("{pkg}: build failed: {status}");
+        }
+        Ok(())
+    }
+
+    /// Start syncing a repo with its output piped back to the caller, which must wait for it
+    /// to finish via [`Worker::wait`].
+    pub(crate) fn sync(&self, repo: &str) -> Result<Child> {
+        self.start(
+            self.daemon("--sync", repo)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .context(format!("{repo}: failed starting sync"))
+    }
+
+    /// Wait for a child started by the worker to finish, killing it along with any processes
+    /// it started if its job is cancelled.
+    pub(crate) async fn wait(&self, job: &JobHandle, child: &mut Child) -> Result<ExitStatus> {
+        let pid = child.id();
+        let result = tokio::select! {
+            status = child.wait() => status.map_err(Into::into),
+            _ = job.cancelled() => {
+                if let Some(pid) = pid {
+                    killpg(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap_or_default();
+                }
+                // reap the killed child before reporting the cancellation
+                match child.wait().await {
+                    Ok(_) => Err(anyhow!("job cancelled")),
+                    Err(e) => Err(e.into()),
+                }
+            }
+        };
+        self.untrack(pid);
+        result
+    }
+
+    /// Kill all running children along with any processes they started.
+    pub(crate) fn kill(&self) {
+        for pgid in self.children.lock().unwrap().drain() {
+            killpg(Pid::from_raw(pgid), Signal::SIGKILL).unwrap_or_default();
+        }
+    }
+}
+
+// Create a command run in its own process group so it can be killed along with any processes
+// it starts.
+fn command<S: AsRef<OsStr>>(program: S) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.stdin(Stdio::null());
+    unsafe {
+        cmd.pre_exec(|| {
+            setpgid(Pid::from_raw(0), Pid::from_raw(0))?;
+            Ok(())
+        });
+    }
+    cmd
+}
+
+/// Build a package within the current process, run by build child processes.
+pub(crate) fn run_build(target: &str, load_config: bool) -> Result<()> {
+    let config = PkgcraftConfig::new("pkgcraft", "", load_config)
+        .context("failed loading pkgcraft config")?;
+    let restrict = restrict::parse::dep(target).map_err(|e| anyhow!("{e}"))?;
+    let pkg =
+        build::best_match(&config, &restrict).context(format!("no matches found: {target}"))?;
+    pkg.build().map_err(|e| anyhow!("{pkg}: build failed: {e}"))
+}
+
+/// Sync a repo within the current process, run by sync child processes.
+pub(crate) fn run_sync(repo: &str, load_config: bool) -> Result<()> {
+    let config = PkgcraftConfig::new("pkgcraft", "", load_config)
+        .context("failed loading pkgcraft config")?;
+    config
+        .repos
+        .sync(vec![repo.to_string()])
+        .map_err(|e| anyhow!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+    use tokio::sync::oneshot;
+    use tokio::time::timeout;
+
+    use crate::jobs::{JobKind, JobManager, JobState};
+    use crate::metrics::Metrics;
+    use crate::store::Store;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let dir = tempdir().unwrap();
+        let store = Store::new(dir.path().join("jobs"));
+        let metrics = Metrics::new().unwrap();
+        let jobs = Arc::new(JobManager::new(1, dir.path().join("logs"), store, metrics).unwrap());
+        let worker = Worker::new(true).unwrap();
+
+        // run a child that won't finish on its own
+        let (started_tx, started_rx) = oneshot::channel();
+        let job = jobs
+            .spawn(JobKind::Build, vec![], {
+                let worker = worker.clone();
+                move |job| async move {
+                    let mut child = worker.start(command("sleep").arg("60"))?;
+                    started_tx.send(child.id()).unwrap();
+                    worker.wait(&job, &mut child).await?;
+                    Ok(())
+                }
+            })
+            .unwrap();
+        let pid = started_rx.await.unwrap().unwrap();
+
+        // cancelling the job kills its running child and process group
+        jobs.cancel(job.id);
+        timeout(Duration::from_secs(5), jobs.wait()).await.unwrap();
+        let job = jobs.get(job.id).unwrap();
+        assert_eq!(job.state, JobState::Cancelled);
+        assert!(killpg(Pid::from_raw(pid as i32), None).is_err());
+        assert!(worker.children.lock().unwrap().is_empty());
+    }
+}
@@ -1,9 +1,21 @@
+use std::fs;
 use std::path::PathBuf;
 use std::str;
+use std::time::Duration;
 
 use assert_cmd::Command as assert_command;
+use futures::StreamExt;
 use once_cell::sync::Lazy;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use regex::Regex;
 use tempfile::Builder;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixListener};
+use tokio::time::{sleep, timeout};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::Server;
+use tonic_health::proto::health_check_response::ServingStatus;
+use tonic_health::proto::{health_client::HealthClient, HealthCheckRequest};
 
 static TARGET_DIR: Lazy<String> = Lazy::new(|| {
     let tmp_dir = PathBuf::from(env!("CARGO_BIN_EXE_arcanist"));
@@ -20,9 +32,12 @@ async fn test_uds() {
     let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
     let socket = socket_path.to_str().unwrap();
 
-    let (mut arcanist, socket) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+    let (mut arcanist, info) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
         .await
         .unwrap();
+    let socket = info.sockets[0].clone();
+    assert_eq!(info.pid, arcanist.id().unwrap());
+    assert_eq!(info.api_version, arcanist::API_VERSION);
 
     let mut cmd = assert_command::cargo_bin("pakt").unwrap();
     let output = cmd
@@ -47,9 +62,10 @@ async fn test_tcp() {
     let args = ["--config-none"];
 
     for addr in ["127.0.0.1:0", "[::]:0"] {
-        let (mut arcanist, socket) = arcanist::spawn(addr, Some(env), Some(args), Some(5))
+        let (mut arcanist, info) = arcanist::spawn(addr, Some(env), Some(args), Some(5))
             .await
             .unwrap();
+        let socket = info.sockets[0].clone();
         let url = format!("http://{}", &socket);
 
         let ver = env!("CARGO_PKG_VERSION");
@@ -71,3 +87,480 @@ async fn test_tcp() {
         arcanist.kill().await.unwrap();
     }
 }
+
+#[tokio::test]
+async fn test_daemon_stop() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none"];
+
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    let (mut arcanist, info) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let socket = info.sockets[0].clone();
+
+    let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+    let output = cmd
+        .arg("--config-none")
+        .arg("-c")
+        .arg(&socket)
+        .args(["daemon", "stop"])
+        .output()
+        .unwrap();
+    assert_eq!(str::from_utf8(&output.stdout).unwrap().trim(), "arcanist stopped");
+
+    // arcanist exits cleanly, removing its socket
+    assert!(arcanist.wait().await.unwrap().success());
+    assert!(!socket_path.exists());
+}
+
+#[tokio::test]
+async fn test_daemon_restart_tcp() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none"];
+
+    let (mut arcanist, info) = arcanist::spawn("127.0.0.1:0", Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let url = format!("http://{}", info.sockets[0]);
+
+    // network instances can't be started again so they aren't stopped
+    let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+    let output = cmd
+        .arg("--config-none")
+        .arg("-c")
+        .arg(&url)
+        .args(["daemon", "restart"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(arcanist.try_wait().unwrap().is_none());
+
+    arcanist.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_request_id() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none"];
+
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+
+    // failed requests report their ID for finding them in arcanist logs
+    let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+    let output = cmd
+        .arg("--config-none")
+        .args(["-c", socket])
+        .args(["jobs", "999999999"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    let re = Regex::new("(?m)^request id: [0-9a-f-]{36}$").unwrap();
+    assert!(re.is_match(stderr), "missing request id: {stderr}");
+
+    // each request is sent with its own ID
+    let mut client = arcanist::Client::connect(&socket).await.unwrap();
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let error = client.job(999999999).await.unwrap_err();
+        let metadata = error.status().unwrap().metadata();
+        let id = metadata.get(arcanist::REQUEST_ID_KEY).unwrap();
+        ids.push(id.to_str().unwrap().to_string());
+    }
+    assert_ne!(ids[0], ids[1]);
+
+    arcanist.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_error_details() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none"];
+
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+
+    // failures are rendered with hints and exit with a status specific to their reason
+    for (subcmd, hint, code) in [
+        (
+            vec!["cancel", "999999999"],
+            "hint: list jobs with `pakt jobs`",
+            66,
+        ),
+        (
+            vec!["repo", "sync", "nonexistent"],
+            "hint: list configured repos",
+            66,
+        ),
+        (
+            vec!["search", "=cat/pkg"],
+            "hint: targets use package dependency syntax",
+            65,
+        ),
+    ] {
+        let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+        let output = cmd
+            .arg("--config-none")
+            .args(["-c", socket])
+            .args(&subcmd)
+            .output()
+            .unwrap();
+        let stderr = str::from_utf8(&output.stderr).unwrap();
+        assert!(stderr.contains(hint), "{subcmd:?}: missing hint: {stderr}");
+        assert_eq!(output.status.code(), Some(code), "{subcmd:?}: {stderr}");
+    }
+
+    arcanist.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none"];
+
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+
+    let mut client = arcanist::Client::connect(&socket).await.unwrap();
+    let ver = env!("CARGO_PKG_VERSION");
+    assert_eq!(client.version().await.unwrap(), format!("arcanist-{ver}"));
+    assert!(client.jobs().await.unwrap().is_empty());
+
+    // failed requests return their structured error details
+    let error = client.job(999999999).await.unwrap_err();
+    let details = error.details().unwrap();
+    assert_eq!(details.reason, arcanist::Reason::JobNotFound);
+    assert_eq!(details.metadata.get("job").unwrap(), "999999999");
+    let error = client.sync(["nonexistent"]).await.unwrap_err();
+    assert_eq!(
+        error.details().unwrap().reason,
+        arcanist::Reason::RepoNotFound
+    );
+
+    // invalid package targets are rejected without queuing jobs
+    let error = client.add(["cat/pkg", "=cat/pkg"]).await.unwrap_err();
+    let details = error.details().unwrap();
+    assert_eq!(details.reason, arcanist::Reason::InvalidTarget);
+    let error = client.remove(["=cat/pkg"]).await.unwrap_err();
+    let details = error.details().unwrap();
+    assert_eq!(details.reason, arcanist::Reason::InvalidTarget);
+    assert!(client.jobs().await.unwrap().is_empty());
+
+    arcanist.kill().await.unwrap();
+}
+
+// Return the sorted packages matching the given targets.
+async fn search(client: &mut arcanist::Client, targets: &[&str]) -> Vec<String> {
+    let stream = client.search(targets.iter().copied()).await.unwrap();
+    let mut pkgs: Vec<_> = stream
+        .map(|r| {
+            let pkg = r.unwrap();
+            let (cat, name, ver) = (pkg.category, pkg.name, pkg.version);
+            format!("{cat}/{name}-{ver}::{}", pkg.repo)
+        })
+        .collect()
+        .await;
+    pkgs.sort();
+    pkgs
+}
+
+#[tokio::test]
+async fn test_search() {
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    // ignore system/user config and run arcanist from build dir, creating repos under the
+    // temporary dir
+    let home = tmp_dir.path().to_str().unwrap();
+    let env: [(&str, &str); 2] = [("PATH", &TARGET_DIR), ("HOME", home)];
+    let args = ["--config-none"];
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let mut client = arcanist::Client::connect(&socket).await.unwrap();
+
+    // populate a new repo with fake packages
+    let repo = client.create_repo("fake").await.unwrap();
+    let repo_path = PathBuf::from(&repo.path);
+    fs::write(repo_path.join("profiles/categories"), "cat\n").unwrap();
+    for (pkg, ver) in [("a", "1"), ("a", "2"), ("b", "1")] {
+        let dir = repo_path.join("cat").join(pkg);
+        fs::create_dir_all(&dir).unwrap();
+        let ebuild = "EAPI=8\nDESCRIPTION=\"testing\"\nSLOT=0\n";
+        fs::write(dir.join(format!("{pkg}-{ver}.ebuild")), ebuild).unwrap();
+    }
+
+    let pkgs = search(&mut client, &["cat/a"]).await;
+    assert_eq!(pkgs, ["cat/a-1::fake", "cat/a-2::fake"]);
+    let pkgs = search(&mut client, &[">=cat/a-2", "cat/b"]).await;
+    assert_eq!(pkgs, ["cat/a-2::fake", "cat/b-1::fake"]);
+    assert!(search(&mut client, &["cat/c"]).await.is_empty());
+
+    arcanist.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none", "--idle-timeout", "1"];
+
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+
+    // arcanist exits on its own without any connections, removing its socket
+    let status = timeout(Duration::from_secs(10), arcanist.wait())
+        .await
+        .expect("arcanist didn't exit")
+        .unwrap();
+    assert!(status.success());
+    assert!(!socket_path.exists());
+}
+
+#[tokio::test]
+async fn test_log_file() {
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+    let log_path = tmp_dir.path().join("logs/arcanist.log");
+    let log_file = log_path.to_str().unwrap();
+
+    // ignore system/user config and run arcanist from build dir, setting log options via env
+    let env: [(&str, &str); 4] = [
+        ("PATH", &TARGET_DIR),
+        ("ARCANIST_VERBOSITY", "1"),
+        ("ARCANIST_LOG_FORMAT", "json"),
+        ("ARCANIST_LOG_FILE", log_file),
+    ];
+    let args = ["--config-none", "--idle-timeout", "1"];
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let status = timeout(Duration::from_secs(10), arcanist.wait())
+        .await
+        .expect("arcanist didn't exit")
+        .unwrap();
+    assert!(status.success());
+
+    // all events are logged to the file as JSON objects
+    let data = fs::read_to_string(&log_path).unwrap();
+    let events: Vec<serde_json::Value> = data
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(events
+        .iter()
+        .any(|e| e["fields"]["message"] == "shutting down"));
+}
+
+#[tokio::test]
+async fn test_health() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none"];
+
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let socket_path = tmp_dir.path().to_owned().join("arcanist.sock");
+    let socket = socket_path.to_str().unwrap();
+
+    let (mut arcanist, _) = arcanist::spawn(&socket, Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+
+    // running instances pass the health check instead of being respawned
+    let connected = arcanist::connect_or_spawn(&socket_path, Some(5), None)
+        .await
+        .unwrap();
+    assert_eq!(connected, socket);
+    arcanist.kill().await.unwrap();
+
+    // instances not serving, e.g. while reloading, are waited on until the timeout expires
+    fs::remove_file(&socket_path).unwrap_or_default();
+    let (mut reporter, health_service) = tonic_health::server::health_reporter();
+    reporter
+        .set_service_status("arcanist.Arcanist", tonic_health::ServingStatus::NotServing)
+        .await;
+    let listener = UnixListener::bind(&socket_path).unwrap();
+    let server = tokio::spawn(
+        Server::builder()
+            .add_service(health_service)
+            .serve_with_incoming(UnixListenerStream::new(listener)),
+    );
+    let err = arcanist::connect_or_spawn(&socket_path, Some(1), None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("not serving"), "{err}");
+
+    // and connected to once they start serving
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(500)).await;
+        reporter
+            .set_service_status("arcanist.Arcanist", tonic_health::ServingStatus::Serving)
+            .await;
+    });
+    let connected = arcanist::connect_or_spawn(&socket_path, Some(5), None)
+        .await
+        .unwrap();
+    assert_eq!(connected, socket);
+    server.abort();
+
+    let (mut arcanist, info) = arcanist::spawn("127.0.0.1:0", Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let url = format!("http://{}", info.sockets[0]);
+    let mut client = HealthClient::connect(url).await.unwrap();
+    for service in ["", "arcanist.Arcanist"] {
+        let request = HealthCheckRequest {
+            service: service.to_string(),
+        };
+        let response = client.check(request).await.unwrap().into_inner();
+        assert_eq!(response.status(), ServingStatus::Serving);
+    }
+
+    arcanist.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let args = ["--config-none", "--metrics", "127.0.0.1:0"];
+
+    let (mut arcanist, info) = arcanist::spawn("127.0.0.1:0", Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let metrics_addr = info.metrics.unwrap();
+
+    let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+    let output = cmd
+        .arg("--config-none")
+        .args(["-c", &info.sockets[0]])
+        .arg("version")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // fetch metrics over plain HTTP
+    let mut stream = TcpStream::connect(&metrics_addr).await.unwrap();
+    let request =
+        format!("GET /metrics HTTP/1.1\r\nHost: {metrics_addr}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(r#"method="arcanist.Arcanist/Version""#));
+    let latency = r#"arcanist_rpc_duration_seconds_count{method="arcanist.Arcanist/Version"} 1"#;
+    assert!(response.contains(latency));
+    assert!(response.contains("arcanist_connections"));
+    assert!(response.contains(r#"arcanist_jobs{state="queued"} 0"#));
+
+    arcanist.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tls() {
+    // ignore system/user config and run arcanist from build dir
+    let env: [(&str, &str); 1] = [("PATH", &TARGET_DIR)];
+    let tmp_dir = Builder::new().prefix("arcanist.").tempdir().unwrap();
+    let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_owned();
+
+    // generate a CA along with server and client certs signed by it
+    let mut params = CertificateParams::new(vec![]);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(params).unwrap();
+    let server =
+        Certificate::from_params(CertificateParams::new(vec!["localhost".into()])).unwrap();
+    let client = Certificate::from_params(CertificateParams::new(vec!["client".into()])).unwrap();
+    fs::write(path("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+    fs::write(
+        path("server.pem"),
+        server.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(path("server.key"), server.serialize_private_key_pem()).unwrap();
+    fs::write(
+        path("client.pem"),
+        client.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(path("client.key"), client.serialize_private_key_pem()).unwrap();
+
+    let (ca_path, cert_path, key_path) = (path("ca.pem"), path("server.pem"), path("server.key"));
+    let args = [
+        "--config-none",
+        "--tls-cert",
+        cert_path.as_str(),
+        "--tls-key",
+        key_path.as_str(),
+        "--tls-client-ca",
+        ca_path.as_str(),
+    ];
+    let (mut arcanist, info) = arcanist::spawn("127.0.0.1:0", Some(env), Some(args), Some(5))
+        .await
+        .unwrap();
+    let socket = info.sockets[0].clone();
+    let port = socket.rsplit(':').next().unwrap();
+    let url = format!("https://localhost:{port}");
+
+    // connecting with a client certificate succeeds
+    let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+    let output = cmd
+        .arg("--config-none")
+        .args(["--tls-ca", &ca_path])
+        .args(["--tls-cert", &path("client.pem")])
+        .args(["--tls-key", &path("client.key")])
+        .args(["-c", &url])
+        .arg("version")
+        .output()
+        .unwrap();
+    let ver = env!("CARGO_PKG_VERSION");
+    let expected = format!("client: pakt-{ver}, server: arcanist-{ver}");
+    assert_eq!(str::from_utf8(&output.stdout).unwrap().trim(), expected);
+
+    // connecting without a client certificate fails
+    let mut cmd = assert_command::cargo_bin("pakt").unwrap();
+    let output = cmd
+        .arg("--config-none")
+        .args(["--tls-ca", &ca_path])
+        .args(["-c", &url])
+        .arg("version")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    arcanist.kill().await.unwrap();
+}